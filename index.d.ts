@@ -3,10 +3,403 @@
 
 /* auto-generated by NAPI-RS */
 
-export declare function loadSshKeyInfo(keyPath: string): string
+export declare function loadSshKeyInfo(keyPath: string, passphrase?: string | undefined | null): string
 export declare function testCertificateDetection(certPath: string): boolean
-export declare function sshConnect(host: string, port: number, username: string, keyPath: string, certPath?: string | undefined | null): Promise<number>
-export declare function sshExec(sessionId: number, command: string): Promise<string>
-export declare function sshForwardPort(sessionId: number, localPort: number, remoteHost: string, remotePort: number): Promise<number>
-export declare function sshUploadFile(sessionId: number, localPath: string, remotePath: string): Promise<void>
+export interface HostKeyPrompt {
+  host: string
+  port: number
+  keyType: string
+  fingerprint: string
+  randomart: string
+  changed: boolean
+  /**
+   * Known-hosts line whose `@cert-authority` covers the host; such lines
+   * are not supported, so the key is treated as unknown.
+   */
+  certAuthority?: string
+}
+export interface PassphrasePrompt {
+  keyPath: string
+  attempt: number
+}
+export interface PasswordPrompt {
+  username: string
+  host: string
+  attempt: number
+}
+export interface KeyboardInteractiveQuestion {
+  prompt: string
+  echo: boolean
+}
+export interface KeyboardInteractivePrompt {
+  username: string
+  host: string
+  name: string
+  instructions: string
+  prompts: Array<KeyboardInteractiveQuestion>
+}
+export interface ConnectOptions {
+  /** One of "yes", "ask", "accept-new" or "no"; defaults to "ask". */
+  strictHostKeyChecking?: string
+  userKnownHostsFiles?: Array<string>
+  globalKnownHostsFiles?: Array<string>
+  /** Asked to confirm unknown or changed host keys; resolves to `true` to trust the key. */
+  onHostKey?: (err: null, prompt: HostKeyPrompt) => boolean | Promise<boolean>
+  /** Agent socket or pipe, as in IdentityAgent; defaults to SSH_AUTH_SOCK. */
+  identityAgent?: string
+  forwardAgent?: boolean
+  passphrase?: string
+  /** Asked for the passphrase of an encrypted key; resolve to `null` to give up. */
+  onPassphrase?: (err: null, prompt: PassphrasePrompt) => string | null | Promise<string | null>
+  password?: string
+  onPassword?: (err: null, prompt: PasswordPrompt) => string | null | Promise<string | null>
+  /** Answers keyboard-interactive challenges; one response per prompt. */
+  onKeyboardInteractive?: (err: null, prompt: KeyboardInteractivePrompt) => Array<string> | null | Promise<Array<string> | null>
+  /** Method names in PreferredAuthentications order. */
+  preferredAuthentications?: Array<string>
+  /** Hosts to tunnel through, first hop first, as in ProxyJump. */
+  jumpHosts?: Array<JumpHost>
+  /** Command whose stdin/stdout carry the connection, with %h, %p, %r and %n expanded. */
+  proxyCommand?: string
+  /** Host name as given by the user, for %n; defaults to the host. */
+  hostAlias?: string
+  /**
+   * Seconds of silence from the server before a keepalive is sent, as in
+   * ServerAliveInterval; 0 or unset sends none.
+   */
+  serverAliveInterval?: number
+  /**
+   * Unanswered keepalives before the connection is dropped, as in
+   * ServerAliveCountMax; defaults to 3.
+   */
+  serverAliveCountMax?: number
+  /**
+   * Told when the connection goes away other than through `sshDisconnect`;
+   * the session is already gone from the registry by then.
+   */
+  onEvent?: (event: SessionEvent) => void
+  /**
+   * Connect again with the same credentials when the connection is lost,
+   * keeping the session id and restoring forwards.
+   */
+  reconnect?: ReconnectOptions
+  /**
+   * Limit on resolving and reaching the host, as in ConnectTimeout; unset or
+   * 0 waits for the operating system.
+   */
+  connectTimeoutMs?: number
+  /**
+   * Limit on the SSH handshake once connected, up to the end of key
+   * exchange: authentication is not included, nor is time spent on host
+   * key prompts. Unset or 0 waits indefinitely.
+   */
+  handshakeTimeoutMs?: number
+  /** Token from `sshCreateCancelToken`; cancelling it abandons the connection attempt. */
+  cancelId?: number
+  /** One of "inet", "inet6" or "any", as in AddressFamily; defaults to "any". */
+  addressFamily?: string
+  /** Local address to connect from, as in BindAddress. */
+  bindAddress?: string
+  /** Network interface to connect through, as in BindInterface; Linux only. */
+  bindInterface?: string
+  /**
+   * Algorithm lists in OpenSSH syntax: a plain list replaces the defaults,
+   * and a leading "+", "-" or "^" appends to, removes from or goes before them.
+   */
+  ciphers?: string
+  kexAlgorithms?: string
+  macs?: string
+  hostKeyAlgorithms?: string
+}
+export interface JumpHost {
+  host: string
+  port?: number
+  username: string
+  /** Defaults to every key in the agent. */
+  identities?: Array<Identity>
+  /** Host key and authentication settings for this hop; its `jumpHosts` is ignored. */
+  options?: ConnectOptions
+}
+export declare function sshConnect(host: string, port: number, username: string, keyPath: string, certPath?: string | undefined | null, options?: ConnectOptions | undefined | null): Promise<number>
+export interface Identity {
+  keyPath?: string
+  /**
+   * OpenSSH certificate for `keyPath`; signed by the agent when it holds
+   * the key, so the key file itself may be missing.
+   */
+  certPath?: string
+  /** SHA256 fingerprint of a key held by the agent. */
+  agentFingerprint?: string
+  /** Offers every agent key and certificate that has not been tried yet. */
+  agent?: boolean
+}
+export interface IdentityAttempt {
+  identity: string
+  accepted: boolean
+  reason?: string
+}
+export interface AuthReport {
+  /** Methods that completed, in order; more than one after a partial success. */
+  methods: Array<string>
+  identity?: string
+  attempts: Array<IdentityAttempt>
+}
+export interface ConnectResult {
+  sessionId: number
+  auth: AuthReport
+}
+export declare function sshConnectWithIdentities(host: string, port: number, username: string, identities: Array<Identity>, options?: ConnectOptions | undefined | null): Promise<ConnectResult>
+export declare function sshCreateCancelToken(): number
+export declare function sshCancel(cancelId: number): void
+export interface ExecResult {
+  stdout: Buffer
+  stderr: Buffer
+  /** Missing when the process was killed by a signal or the server never reported it. */
+  exitCode?: number
+  exitSignal?: string
+  coreDumped: boolean
+  errorMessage?: string
+}
+export interface ExecExit {
+  exitCode?: number
+  exitSignal?: string
+  coreDumped: boolean
+  errorMessage?: string
+}
+export declare function sshExec(sessionId: number, command: string): Promise<ExecResult>
+export declare function sshExecStream(sessionId: number, command: string, onStdout: (chunk: Buffer) => void, onStderr: (chunk: Buffer) => void): Promise<ExecExit>
+export interface ExecOptions {
+  /** Written to the command's stdin, followed by EOF. */
+  stdin?: Buffer
+  env?: Record<string, string>
+  /** On expiry the command gets `cancelSignal` and the channel is closed. */
+  timeoutMs?: number
+  pty?: boolean
+  /** With `pty`; defaults to "xterm-256color". */
+  term?: string
+  cols?: number
+  rows?: number
+  /** Token from `sshCreateCancelToken`, for `sshCancel` or `sshCancelExec`. */
+  cancelId?: number
+  /** Signal name sent on timeout; defaults to "TERM". */
+  cancelSignal?: string
+  onStdout?: (chunk: Buffer) => void
+  onStderr?: (chunk: Buffer) => void
+}
+export declare function sshCancelExec(cancelId: number, signal?: string | undefined | null): void
+export declare function sshExecWithOptions(sessionId: number, command: string, options?: ExecOptions | undefined | null): Promise<ExecResult>
+export interface ShellOptions {
+  /** Defaults to "xterm-256color". */
+  term?: string
+  cols?: number
+  rows?: number
+  /** Terminal modes by RFC 4254 name, e.g. `{ ECHO: 1, TTY_OP_ISPEED: 38400 }`. */
+  modes?: Record<string, number>
+}
+export declare function sshOpenShell(sessionId: number, options: ShellOptions | undefined | null, onData: (chunk: Buffer) => void, onExit: (exit: ExecExit) => void): Promise<number>
+export declare function sshShellWrite(shellId: number, data: Buffer): Promise<void>
+export declare function sshShellResize(shellId: number, cols: number, rows: number): Promise<void>
+export declare function sshShellClose(shellId: number): Promise<void>
+export declare function sshForwardPort(sessionId: number, localPort: number, remoteHost: string, remotePort: number): Promise<ForwardInfo>
+export declare function sshDynamicForward(sessionId: number, bindAddr: string, port: number): Promise<ForwardInfo>
+export declare function sshRemoteForward(sessionId: number, remoteAddr: string, remotePort: number, localHost: string, localPort: number): Promise<ForwardInfo>
+export declare function sshCancelRemoteForward(sessionId: number, remoteAddr: string, remotePort: number): Promise<void>
+export declare function sshForwardSocket(sessionId: number, localPort: number, remoteSocketPath: string): Promise<ForwardInfo>
+export declare function sshRemoteForwardSocket(sessionId: number, remoteSocketPath: string, localHost: string, localPort: number): Promise<ForwardInfo>
+export declare function sshCancelRemoteForwardSocket(sessionId: number, remoteSocketPath: string): Promise<void>
+export interface ForwardInfo {
+  forwardId: number
+  sessionId: number
+  /** One of "local", "dynamic", "socket", "remote" or "remote-socket". */
+  kind: string
+  /** Where connections are accepted: a local or remote address, or a remote socket path. */
+  listenAddress: string
+  listenPort?: number
+  target: string
+  activeConnections: number
+  totalConnections: number
+  /** Bytes read from the local side and sent over SSH. */
+  bytesSent: number
+  bytesReceived: number
+}
+export declare function sshListForwards(sessionId?: number | undefined | null): Array<ForwardInfo>
+export declare function sshCloseForward(forwardId: number): Promise<void>
+export interface SftpStat {
+  size?: number
+  uid?: number
+  gid?: number
+  /** Full `st_mode`, file type bits included. */
+  mode?: number
+  /** Seconds since the epoch. */
+  atime?: number
+  mtime?: number
+  isFile: boolean
+  isDirectory: boolean
+  isSymlink: boolean
+}
+export interface SftpEntry {
+  name: string
+  /** The `ls -l` style line the server sent, if any. */
+  longName: string
+  stat: SftpStat
+}
+export declare function sshSftpStat(sessionId: number, path: string): Promise<SftpStat | null>
+export declare function sshSftpLstat(sessionId: number, path: string): Promise<SftpStat | null>
+export declare function sshSftpReaddir(sessionId: number, path: string): Promise<Array<SftpEntry>>
+export declare function sshSftpMkdir(sessionId: number, path: string, recursive?: boolean | undefined | null, mode?: number | undefined | null): Promise<void>
+export declare function sshSftpRmdir(sessionId: number, path: string): Promise<void>
+export declare function sshSftpRemove(sessionId: number, path: string): Promise<void>
+export declare function sshSftpRename(sessionId: number, oldPath: string, newPath: string): Promise<void>
+export declare function sshSftpSymlink(sessionId: number, target: string, linkPath: string): Promise<void>
+export declare function sshSftpReadlink(sessionId: number, path: string): Promise<string>
+export declare function sshSftpRealpath(sessionId: number, path: string): Promise<string>
+export declare function sshSftpChmod(sessionId: number, path: string, mode: number): Promise<void>
+export declare function sshSftpChown(sessionId: number, path: string, uid: number, gid: number): Promise<void>
+export declare function sshSftpUtimes(sessionId: number, path: string, atime: number, mtime: number): Promise<void>
+export declare function sshSftpRead(sessionId: number, path: string, offset: number | undefined | null, length: number): Promise<Buffer>
+export interface TransferOptions {
+  /** Continue from the destination's current size instead of starting over. */
+  resume?: boolean
+  /** Outstanding read or write requests; defaults to 32. */
+  requests?: number
+  /** Token from `sshCreateCancelToken`, for `sshCancel`. */
+  cancelId?: number
+  /**
+   * Hash the data while streaming and compare it with the remote file's
+   * SHA-256 afterwards; a mismatch is reported in `mismatch`.
+   */
+  verify?: boolean
+  /**
+   * Uploads only: write a temporary sibling, fsync it and rename it over
+   * the target, so the target is never seen half written.
+   */
+  atomic?: boolean
+  /** With `atomic`, give the new file the permissions of the one it replaces. */
+  keepPermissions?: boolean
+  onProgress?: (progress: TransferProgress) => void
+}
+export interface TransferProgress {
+  /** Bytes at the destination so far, a resumed prefix included. */
+  transferred: number
+  total: number
+}
+export interface TransferResult {
+  size: number
+  /** Bytes moved by this call. */
+  transferred: number
+  /** Where a resumed transfer picked up; 0 when it started from scratch. */
+  resumedFrom: number
+  /** SHA-256 of the whole file, when verified. */
+  sha256?: string
+  /** How the remote hash was obtained: "check-file" or "sha256sum". */
+  verifiedWith?: string
+  /**
+   * Set when verification found the two copies differ. An upload then
+   * removes what it wrote, so an atomic one leaves the target untouched;
+   * a download keeps the local copy.
+   */
+  mismatch?: IntegrityMismatch
+}
+export interface IntegrityMismatch {
+  localSha256: string
+  remoteSha256: string
+}
+export declare function sshUploadFile(sessionId: number, localPath: string, remotePath: string, options?: TransferOptions | undefined | null): Promise<TransferResult>
+export declare function sshDownloadFile(sessionId: number, remotePath: string, localPath: string, options?: TransferOptions | undefined | null): Promise<TransferResult>
+export interface SyncOptions {
+  /**
+   * Globs over paths relative to the source directory: `*` and `?` stop at
+   * "/", `**` does not, and a pattern without "/" matches names at any depth.
+   * When given, only matching files are copied.
+   */
+  include?: Array<string>
+  /** Matching files are skipped and matching directories are not descended into. */
+  exclude?: Array<string>
+  /** Copy permission bits and access/modification times to the destination. */
+  preserve?: boolean
+  /**
+   * Skip files that already match at the destination: "size-mtime" compares
+   * size and modification time, "hash" compares size and SHA-256.
+   */
+  delta?: string
+  /** Files transferred at once; defaults to 4. */
+  concurrency?: number
+  /** Outstanding requests per file; defaults to 32. */
+  requests?: number
+  /** Token from `sshCreateCancelToken`, for `sshCancel`. */
+  cancelId?: number
+  onProgress?: (progress: SyncProgress) => void
+}
+export interface SyncProgress {
+  /** Files transferred or skipped so far. */
+  filesDone: number
+  filesTotal: number
+  bytesDone: number
+  bytesTotal: number
+}
+export interface SyncResult {
+  filesTransferred: number
+  /** Files left alone because delta mode found them unchanged. */
+  filesSkipped: number
+  bytesTransferred: number
+  directoriesCreated: number
+}
+export declare function sshUploadDir(sessionId: number, localDir: string, remoteDir: string, options?: SyncOptions | undefined | null): Promise<SyncResult>
+export declare function sshDownloadDir(sessionId: number, remoteDir: string, localDir: string, options?: SyncOptions | undefined | null): Promise<SyncResult>
 export declare function sshDisconnect(sessionId: number): Promise<void>
+export declare function sshSessionLog(sessionId: number): Array<string>
+export interface NegotiatedAlgorithms {
+  kex: string
+  hostKey: string
+  cipher: string
+  /** Client to server; unset for AEAD ciphers, which carry their own integrity. */
+  mac?: string
+}
+export interface SessionInfo {
+  sessionId: number
+  host: string
+  port: number
+  username: string
+  /** Authentication methods that completed, in order. */
+  authMethods: Array<string>
+  identity?: string
+  /** Jump hosts the connection goes through, first hop first. */
+  jumpHosts: Array<string>
+  /** Milliseconds since the epoch, as from `Date.now()`. */
+  startedAt: number
+  forwards: Array<ForwardInfo>
+  shells: number
+  execs: number
+  sftp: boolean
+  /** Session channels in use: commands, shells, SFTP and forwarded connections. */
+  channels: number
+  /** Algorithms agreed in the latest key exchange with the target host. */
+  algorithms?: NegotiatedAlgorithms
+}
+export interface ReconnectOptions {
+  /** Attempts before the session is given up; defaults to 10. */
+  attempts?: number
+  /** Wait before the first attempt, doubled after each failure; defaults to 1000. */
+  delayMs?: number
+  /** Upper bound for the wait; defaults to 30000. */
+  maxDelayMs?: number
+}
+export declare function sshListSessions(): Array<SessionInfo>
+export declare function sshSessionInfo(sessionId: number): SessionInfo
+export interface SessionEvent {
+  sessionId: number
+  /**
+   * "disconnected" when the connection failed, "keepalive-timeout" when
+   * the server stopped answering keepalives, or "server-disconnect".
+   * Sessions that reconnect also report "reconnecting", "reconnected" and
+   * "reconnect-failed".
+   */
+  kind: string
+  /** SSH_MSG_DISCONNECT reason code, for "server-disconnect". */
+  reasonCode?: number
+  description?: string
+  /** Reconnect attempt, counting from 1. */
+  attempt?: number
+  /** Whether the session is gone; false while it is being reconnected. */
+  closed: boolean
+}
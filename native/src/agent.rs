@@ -0,0 +1,174 @@
+use russh::keys::agent::client::AgentClient;
+use russh::keys::{Certificate, PublicKey};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub trait AgentSocket: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> AgentSocket for S {}
+
+pub type Agent = AgentClient<Box<dyn AgentSocket>>;
+
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+const MAX_REPLY: usize = 256 * 1024;
+
+#[cfg(windows)]
+const DEFAULT_AGENT_PIPE: &str = r"\\.\pipe\openssh-ssh-agent";
+
+// Mirrors IdentityAgent: "none" disables the agent and "SSH_AUTH_SOCK" (or no
+// value) falls back to the environment.
+pub fn socket_path(identity_agent: Option<&str>) -> Option<String> {
+    match identity_agent.map(str::trim) {
+        Some("none") => None,
+        Some("SSH_AUTH_SOCK") | Some("") | None => {
+            let from_env = std::env::var("SSH_AUTH_SOCK").ok().filter(|s| !s.is_empty());
+            #[cfg(windows)]
+            let from_env = from_env.or_else(|| Some(DEFAULT_AGENT_PIPE.to_string()));
+            from_env
+        }
+        Some(path) => Some(crate::known_hosts::expand_path(path).to_string_lossy().into_owned()),
+    }
+}
+
+#[cfg(unix)]
+pub async fn open(path: &str) -> std::io::Result<Box<dyn AgentSocket>> {
+    Ok(Box::new(tokio::net::UnixStream::connect(path).await?))
+}
+
+#[cfg(windows)]
+pub async fn open(path: &str) -> std::io::Result<Box<dyn AgentSocket>> {
+    Ok(Box::new(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?))
+}
+
+#[derive(Clone)]
+pub enum AgentIdentity {
+    Key(PublicKey),
+    Certificate { cert: Box<Certificate>, comment: String },
+}
+
+// Lists the agent's identities in its order. russh keeps only the algorithm
+// of a certificate, so the answer is read here to keep certificates whole;
+// entries of unknown types are skipped.
+pub async fn identities<S: AsyncRead + AsyncWrite + Unpin>(socket: &mut S) -> io::Result<Vec<AgentIdentity>> {
+    socket.write_all(&[0, 0, 0, 1, REQUEST_IDENTITIES]).await?;
+    let len = socket.read_u32().await? as usize;
+    if len > MAX_REPLY {
+        return Err(invalid("agent reply too long"));
+    }
+    let mut reply = vec![0; len];
+    socket.read_exact(&mut reply).await?;
+    parse_identities(&reply)
+}
+
+fn parse_identities(reply: &[u8]) -> io::Result<Vec<AgentIdentity>> {
+    let Some((&IDENTITIES_ANSWER, mut rest)) = reply.split_first() else {
+        return Err(invalid("agent refused to list identities"));
+    };
+    let count = u32::from_be_bytes(take(&mut rest, 4)?.try_into().unwrap_or_default());
+    let mut identities = Vec::new();
+    for _ in 0..count {
+        let blob = string(&mut rest)?;
+        let comment = String::from_utf8_lossy(string(&mut rest)?).into_owned();
+        let mut algorithm = blob;
+        if string(&mut algorithm)?.ends_with(b"-cert-v01@openssh.com") {
+            if let Ok(cert) = Certificate::from_bytes(blob) {
+                identities.push(AgentIdentity::Certificate { cert: Box::new(cert), comment });
+            }
+        } else if let Ok(mut key) = PublicKey::from_bytes(blob) {
+            key.set_comment(comment);
+            identities.push(AgentIdentity::Key(key));
+        }
+    }
+    Ok(identities)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid("truncated agent reply"));
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn string<'a>(data: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = u32::from_be_bytes(take(data, 4)?.try_into().unwrap_or_default());
+    take(data, len as usize)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIBt2pZdbTcjqRjW98BaAC4FGa5u4NLgphTtmHdubBUPf user@test";
+    const CERT: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIPKRBXmFA2qRNI6TCyzIt/X4mTIqGSLy9MTuC8Em12JoAAAAIBt2pZdbTcjqRjW98BaAC4FGa5u4NLgphTtmHdubBUPfAAAAAAAAAAAAAAABAAAAB3Rlc3QtaWQAAAAJAAAABWFsaWNlAAAAAGlVuQAAAAAAfCRfAAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIJepJ9Wr7eh2Vi6owo6kiM/oX5MCU4EY4Q11lUVP1/R5AAAAUwAAAAtzc2gtZWQyNTUxOQAAAECfy+j4ajY9iUr6Co53FySY7guWAIM5WSoTHQlbN01XdlvA4oKuxS5Q9VJNbZjIgNeiS6kO78DpaPCog1KQegAN user@test";
+
+    fn answer(entries: &[(Vec<u8>, &str)]) -> Vec<u8> {
+        let mut reply = vec![IDENTITIES_ANSWER];
+        reply.extend((entries.len() as u32).to_be_bytes());
+        for (blob, comment) in entries {
+            reply.extend((blob.len() as u32).to_be_bytes());
+            reply.extend(blob);
+            reply.extend((comment.len() as u32).to_be_bytes());
+            reply.extend(comment.as_bytes());
+        }
+        reply
+    }
+
+    #[test]
+    fn keeps_certificates_whole() {
+        let key = PublicKey::from_openssh(KEY).unwrap();
+        let cert = Certificate::from_openssh(CERT).unwrap();
+        let reply = answer(&[
+            (key.to_bytes().unwrap(), "laptop"),
+            (b"\0\0\0\x0bunknown-key".to_vec(), "skipped"),
+            (cert.to_bytes().unwrap(), "issued"),
+        ]);
+
+        let identities = parse_identities(&reply).unwrap();
+        assert_eq!(identities.len(), 2);
+        match &identities[0] {
+            AgentIdentity::Key(parsed) => {
+                assert_eq!(parsed.key_data(), key.key_data());
+                assert_eq!(parsed.comment(), "laptop");
+            }
+            AgentIdentity::Certificate { .. } => panic!("expected a key"),
+        }
+        match &identities[1] {
+            AgentIdentity::Certificate { cert: parsed, comment } => {
+                assert_eq!(parsed.signature(), cert.signature());
+                assert_eq!(parsed.valid_principals(), ["alice"]);
+                assert_eq!(comment, "issued");
+            }
+            AgentIdentity::Key(_) => panic!("expected a certificate"),
+        }
+    }
+
+    #[test]
+    fn rejects_failures_and_truncated_replies() {
+        assert!(parse_identities(&[5]).is_err());
+        let mut reply = answer(&[(PublicKey::from_openssh(KEY).unwrap().to_bytes().unwrap(), "laptop")]);
+        reply.truncate(reply.len() - 2);
+        assert!(parse_identities(&reply).is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_identities() {
+        let (mut client, mut agent) = tokio::io::duplex(4096);
+        let reply = answer(&[]);
+        let serve = async move {
+            let mut request = [0; 5];
+            agent.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [0, 0, 0, 1, REQUEST_IDENTITIES]);
+            agent.write_u32(reply.len() as u32).await.unwrap();
+            agent.write_all(&reply).await.unwrap();
+        };
+        let (identities, _) = tokio::join!(identities(&mut client), serve);
+        assert!(identities.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::glob::Pattern;
+use crate::ConnectOptions;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use parking_lot::Mutex;
+use russh::{cipher, kex, mac, Names, Preferred};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+// Pseudo-algorithms advertising extensions rather than key exchanges; they
+// are kept whatever KexAlgorithms says.
+const KEX_EXTENSIONS: &[kex::Name] = &[
+    kex::EXTENSION_SUPPORT_AS_CLIENT,
+    kex::EXTENSION_SUPPORT_AS_SERVER,
+    kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+    kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER,
+];
+
+#[napi(object)]
+#[derive(Clone)]
+pub struct NegotiatedAlgorithms {
+    pub kex: String,
+    pub host_key: String,
+    pub cipher: String,
+    /// Client to server; unset for AEAD ciphers, which carry their own integrity.
+    pub mac: Option<String>,
+}
+
+// Filled in by the handler after every key exchange, so it follows rekeys
+// and reconnects.
+pub(crate) type Negotiated = Arc<Mutex<Option<NegotiatedAlgorithms>>>;
+
+pub(crate) fn record(negotiated: &Negotiated, names: &Names) {
+    let cipher = names.cipher.as_ref();
+    let aead = cipher == cipher::CHACHA20_POLY1305.as_ref() || cipher.ends_with("-gcm@openssh.com");
+    *negotiated.lock() = Some(NegotiatedAlgorithms {
+        kex: names.kex.as_ref().to_string(),
+        host_key: names.key.as_str().to_string(),
+        cipher: cipher.to_string(),
+        mac: (!aead).then(|| names.client_mac.as_ref().to_string()),
+    });
+}
+
+// Algorithm preferences from Ciphers, KexAlgorithms, MACs and
+// HostKeyAlgorithms; whatever is unset keeps russh's defaults.
+pub(crate) fn preferred(options: &ConnectOptions) -> Result<Preferred> {
+    let defaults = Preferred::default();
+    let mut preferred = Preferred::default();
+
+    if let Some(spec) = &options.kex_algorithms {
+        let default: Vec<String> = defaults.kex.iter()
+            .filter(|name| !KEX_EXTENSIONS.contains(name))
+            .map(|name| name.as_ref().to_string())
+            .collect();
+        let supported = names(kex::ALL_KEX_ALGORITHMS.iter().map(|name| name.as_ref()));
+        let mut kex: Vec<kex::Name> = apply("KexAlgorithms", spec, &default, &supported)?.iter()
+            .filter_map(|name| kex::Name::try_from(name.as_str()).ok())
+            .collect();
+        kex.extend(defaults.kex.iter().filter(|name| KEX_EXTENSIONS.contains(name)));
+        preferred.kex = Cow::Owned(kex);
+    }
+
+    if let Some(spec) = &options.host_key_algorithms {
+        // russh verifies every host key type it can negotiate, so its
+        // defaults are also everything it supports.
+        let default = names(defaults.key.iter().map(|algorithm| algorithm.as_str()));
+        let key = apply("HostKeyAlgorithms", spec, &default, &default)?.iter()
+            .filter_map(|name| defaults.key.iter().find(|algorithm| algorithm.as_str() == name).cloned())
+            .collect();
+        preferred.key = Cow::Owned(key);
+    }
+
+    if let Some(spec) = &options.ciphers {
+        let default = names(defaults.cipher.iter().map(|name| name.as_ref()));
+        let supported = names(cipher::ALL_CIPHERS.iter().map(|name| name.as_ref()));
+        let cipher = apply("Ciphers", spec, &default, &supported)?.iter()
+            .filter_map(|name| cipher::Name::try_from(name.as_str()).ok())
+            .collect();
+        preferred.cipher = Cow::Owned(cipher);
+    }
+
+    if let Some(spec) = &options.macs {
+        let default = names(defaults.mac.iter().map(|name| name.as_ref()));
+        let supported = names(mac::ALL_MAC_ALGORITHMS.iter().map(|name| name.as_ref()));
+        let mac = apply("MACs", spec, &default, &supported)?.iter()
+            .filter_map(|name| mac::Name::try_from(name.as_str()).ok())
+            .collect();
+        preferred.mac = Cow::Owned(mac);
+    }
+
+    Ok(preferred)
+}
+
+// Algorithm names, leaving out the unencrypted placeholders.
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    names.filter(|name| !matches!(*name, "none" | "clear")).map(str::to_string).collect()
+}
+
+// Applies an algorithm list as OpenSSH reads it: a plain list replaces the
+// defaults, "+" appends to them, "-" removes from them and "^" moves the given
+// algorithms to the front. Names may use '*' and '?' and are looked up among
+// the supported algorithms; those russh lacks are skipped, so that lists
+// written for OpenSSH still work.
+fn apply(option: &str, spec: &str, default: &[String], supported: &[String]) -> Result<Vec<String>> {
+    let (modifier, list) = match spec.trim().chars().next() {
+        Some(modifier @ ('+' | '-' | '^')) => (Some(modifier), &spec.trim()[1..]),
+        _ => (None, spec.trim()),
+    };
+
+    let mut named: Vec<String> = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let pattern = Pattern::new(name);
+        for algorithm in supported.iter().filter(|algorithm| pattern.matches(algorithm)) {
+            if !named.contains(algorithm) {
+                named.push(algorithm.clone());
+            }
+        }
+    }
+
+    let without = |list: &[String], drop: &[String]| {
+        list.iter().filter(|name| !drop.contains(name)).cloned().collect::<Vec<_>>()
+    };
+    let algorithms = match modifier {
+        Some('+') => [default.to_vec(), without(&named, default)].concat(),
+        Some('-') => without(default, &named),
+        Some('^') => [named.clone(), without(default, &named)].concat(),
+        _ => named,
+    };
+    if algorithms.is_empty() {
+        return Err(napi::Error::new(Status::InvalidArg, format!("{}: no supported algorithms in {}", option, spec)));
+    }
+    Ok(algorithms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn apply_to_defaults(spec: &str) -> Result<Vec<String>> {
+        let default = list(&["aes256-ctr", "aes128-ctr", "chacha20-poly1305@openssh.com"]);
+        let supported = list(&["aes256-ctr", "aes192-ctr", "aes128-ctr", "aes128-cbc", "chacha20-poly1305@openssh.com"]);
+        apply("Ciphers", spec, &default, &supported)
+    }
+
+    #[test]
+    fn plain_list_replaces_defaults() {
+        assert_eq!(apply_to_defaults("aes128-ctr,aes256-ctr").unwrap(), ["aes128-ctr", "aes256-ctr"]);
+        // Supported but not a default is fine; duplicates collapse.
+        assert_eq!(apply_to_defaults(" aes128-cbc , aes128-cbc ").unwrap(), ["aes128-cbc"]);
+    }
+
+    #[test]
+    fn plus_appends_missing_names() {
+        assert_eq!(
+            apply_to_defaults("+aes128-cbc,aes256-ctr").unwrap(),
+            ["aes256-ctr", "aes128-ctr", "chacha20-poly1305@openssh.com", "aes128-cbc"]
+        );
+    }
+
+    #[test]
+    fn minus_removes_names_and_wildcards() {
+        assert_eq!(apply_to_defaults("-aes128-ctr").unwrap(), ["aes256-ctr", "chacha20-poly1305@openssh.com"]);
+        assert_eq!(apply_to_defaults("-aes*").unwrap(), ["chacha20-poly1305@openssh.com"]);
+        assert_eq!(apply_to_defaults("-aes???-ctr").unwrap(), ["chacha20-poly1305@openssh.com"]);
+    }
+
+    #[test]
+    fn caret_moves_names_to_the_front() {
+        assert_eq!(
+            apply_to_defaults("^chacha20-poly1305@openssh.com").unwrap(),
+            ["chacha20-poly1305@openssh.com", "aes256-ctr", "aes128-ctr"]
+        );
+        // A name outside the defaults is still put first.
+        assert_eq!(
+            apply_to_defaults("^aes128-cbc").unwrap(),
+            ["aes128-cbc", "aes256-ctr", "aes128-ctr", "chacha20-poly1305@openssh.com"]
+        );
+    }
+
+    #[test]
+    fn wildcards_expand_in_supported_order() {
+        assert_eq!(apply_to_defaults("aes*-ctr").unwrap(), ["aes256-ctr", "aes192-ctr", "aes128-ctr"]);
+    }
+
+    #[test]
+    fn unknown_names_are_skipped_but_not_alone() {
+        assert_eq!(apply_to_defaults("3des-cbc,aes128-ctr").unwrap(), ["aes128-ctr"]);
+        for spec in ["3des-cbc", "3des-cbc,none", "", "-*"] {
+            let error = apply_to_defaults(spec).unwrap_err();
+            assert_eq!(error.status, Status::InvalidArg, "{}", spec);
+            assert!(error.reason.starts_with("Ciphers: no supported algorithms"), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn plus_of_unknown_names_keeps_defaults() {
+        assert_eq!(
+            apply_to_defaults("+3des-cbc").unwrap(),
+            ["aes256-ctr", "aes128-ctr", "chacha20-poly1305@openssh.com"]
+        );
+    }
+}
@@ -0,0 +1,553 @@
+use crate::agent::{self, Agent, AgentIdentity};
+use crate::callback::{self, Prompt};
+use crate::identity;
+use crate::PassphrasePrompt;
+use napi_derive::napi;
+use russh::client::{self, AuthResult, KeyboardInteractiveAuthResponse};
+use russh::keys::agent::client::AgentClient;
+use russh::keys::{Algorithm, Certificate, HashAlg, PrivateKeyWithHashAlg, PublicKey};
+use russh::{AgentAuthError, CryptoVec, MethodKind, MethodSet, Signer};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+const PASSWORD_ATTEMPTS: u32 = 3;
+
+const DEFAULT_PREFERRED: [MethodKind; 3] = [
+    MethodKind::PublicKey,
+    MethodKind::KeyboardInteractive,
+    MethodKind::Password,
+];
+
+#[napi(object)]
+pub struct PasswordPrompt {
+    pub username: String,
+    pub host: String,
+    pub attempt: u32,
+}
+
+#[napi(object)]
+pub struct KeyboardInteractiveQuestion {
+    pub prompt: String,
+    pub echo: bool,
+}
+
+#[napi(object)]
+pub struct KeyboardInteractivePrompt {
+    pub username: String,
+    pub host: String,
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<KeyboardInteractiveQuestion>,
+}
+
+// Boxing the signing future keeps the connect future provably `Send`; the
+// generic one from russh trips a higher-ranked lifetime limitation in rustc.
+struct AgentSigner<'a>(&'a mut Agent);
+
+impl Signer for AgentSigner<'_> {
+    type Error = AgentAuthError;
+
+    #[allow(refining_impl_trait)]
+    fn auth_publickey_sign(
+        &mut self,
+        key: &PublicKey,
+        hash_alg: Option<HashAlg>,
+        to_sign: CryptoVec,
+    ) -> Pin<Box<dyn Future<Output = Result<CryptoVec, AgentAuthError>> + Send + '_>> {
+        let key = key.clone();
+        Box::pin(async move {
+            self.0
+                .sign_request(&key, hash_alg, to_sign)
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct Identity {
+    pub key_path: Option<String>,
+    /// OpenSSH certificate for `keyPath`; signed by the agent when it holds
+    /// the key, so the key file itself may be missing.
+    pub cert_path: Option<String>,
+    /// SHA256 fingerprint of a key held by the agent.
+    pub agent_fingerprint: Option<String>,
+    /// Offers every agent key and certificate that has not been tried yet.
+    pub agent: Option<bool>,
+}
+
+impl Identity {
+    fn label(&self) -> String {
+        match (&self.key_path, &self.cert_path, &self.agent_fingerprint) {
+            (Some(key_path), Some(cert_path), _) => format!("{} ({})", key_path, cert_path),
+            (Some(key_path), None, _) => key_path.clone(),
+            (None, _, Some(fingerprint)) => format!("agent {}", fingerprint),
+            (None, _, None) => "agent".to_string(),
+        }
+    }
+}
+
+#[napi(object)]
+pub struct IdentityAttempt {
+    pub identity: String,
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct AuthReport {
+    /// Methods that completed, in order; more than one after a partial success.
+    pub methods: Vec<String>,
+    pub identity: Option<String>,
+    pub attempts: Vec<IdentityAttempt>,
+}
+
+impl AuthReport {
+    fn record(&mut self, identity: String, result: Result<&AuthResult, String>) {
+        let (accepted, reason) = match result {
+            Ok(AuthResult::Success) => (true, None),
+            Ok(AuthResult::Failure { partial_success: true, .. }) => (true, Some("partial success".to_string())),
+            Ok(AuthResult::Failure { .. }) => (false, Some("rejected by server".to_string())),
+            Err(reason) => (false, Some(reason)),
+        };
+        if accepted {
+            self.identity = Some(identity.clone());
+        }
+        self.attempts.push(IdentityAttempt { identity, accepted, reason });
+    }
+
+    fn summary(&self) -> String {
+        self.attempts
+            .iter()
+            .filter(|a| !a.accepted)
+            .map(|a| format!("{}: {}", a.identity, a.reason.as_deref().unwrap_or("rejected")))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub host: String,
+    pub identities: Vec<Identity>,
+    pub agent_path: Option<String>,
+    pub passphrase: Option<String>,
+    pub on_passphrase: Option<Prompt<PassphrasePrompt>>,
+    pub password: Option<String>,
+    pub on_password: Option<Prompt<PasswordPrompt>>,
+    pub on_keyboard_interactive: Option<Prompt<KeyboardInteractivePrompt>>,
+    pub preferred: Vec<MethodKind>,
+}
+
+pub fn preferred_methods(names: Option<&[String]>) -> Vec<MethodKind> {
+    match names {
+        Some(names) => names
+            .iter()
+            .filter_map(|name| MethodKind::from_str(name.trim()).ok())
+            .collect(),
+        None => DEFAULT_PREFERRED.to_vec(),
+    }
+}
+
+fn method_names(methods: &MethodSet) -> String {
+    methods
+        .iter()
+        .map(<&str>::from)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+async fn rsa_hash<H: client::Handler>(
+    handle: &client::Handle<H>,
+    key: &PublicKey,
+) -> anyhow::Result<Option<HashAlg>> {
+    if !matches!(key.algorithm(), Algorithm::Rsa { .. }) {
+        return Ok(None);
+    }
+    Ok(handle.best_supported_rsa_hash().await?.flatten())
+}
+
+fn is_rejection(result: &AuthResult) -> bool {
+    matches!(result, AuthResult::Failure { partial_success: false, .. })
+}
+
+fn same_fingerprint(key: &PublicKey, fingerprint: &str) -> bool {
+    let normalize = |f: &str| f.trim().trim_start_matches("SHA256:").trim_end_matches('=').to_string();
+    normalize(&key.fingerprint(HashAlg::Sha256).to_string()) == normalize(fingerprint)
+}
+
+struct AgentKeys {
+    agent: Agent,
+    identities: Vec<AgentIdentity>,
+}
+
+impl AgentKeys {
+    async fn connect(path: &str) -> anyhow::Result<Self> {
+        let mut socket = agent::open(path).await?;
+        let identities = agent::identities(&mut socket).await?;
+        Ok(AgentKeys { agent: AgentClient::connect(socket), identities })
+    }
+
+    fn find(&self, matches: impl Fn(&PublicKey) -> bool) -> Option<PublicKey> {
+        self.identities.iter().find_map(|identity| match identity {
+            AgentIdentity::Key(key) if matches(key) => Some(key.clone()),
+            _ => None,
+        })
+    }
+
+    // Whether the agent can sign for `key`, as a plain key or a certificate's.
+    fn holds(&self, key: &PublicKey) -> bool {
+        self.identities.iter().any(|identity| match identity {
+            AgentIdentity::Key(held) => held.key_data() == key.key_data(),
+            AgentIdentity::Certificate { cert, .. } => cert.public_key() == key.key_data(),
+        })
+    }
+}
+
+impl Credentials {
+    // Walks the server's advertised methods in PreferredAuthentications order,
+    // following partial successes until the server lets us in or nothing is left.
+    pub async fn authenticate<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+    ) -> anyhow::Result<AuthReport> {
+        let mut report = AuthReport::default();
+        let mut remaining = match handle.authenticate_none(self.username.as_str()).await? {
+            AuthResult::Success => return Ok(report),
+            AuthResult::Failure { remaining_methods, .. } => remaining_methods,
+        };
+        let mut tried = Vec::new();
+
+        loop {
+            let next = self
+                .preferred
+                .iter()
+                .copied()
+                .find(|m| remaining.contains(m) && !tried.contains(m));
+            let Some(method) = next.filter(|_| !handle.is_closed()) else {
+                let mut message = match handle.is_closed() {
+                    true => "server stopped accepting authentication attempts".to_string(),
+                    false => format!("no more authentication methods to try (server allows {})", method_names(&remaining)),
+                };
+                let summary = report.summary();
+                if !summary.is_empty() {
+                    message = format!("{} [{}]", message, summary);
+                }
+                anyhow::bail!(message);
+            };
+            tried.push(method);
+
+            let result = match method {
+                MethodKind::PublicKey => self.publickey(handle, &mut report).await?,
+                MethodKind::Password => self.password(handle).await?,
+                MethodKind::KeyboardInteractive => self.keyboard_interactive(handle).await?,
+                _ => None,
+            };
+
+            match result {
+                Some(AuthResult::Success) => {
+                    report.methods.push(<&str>::from(&method).to_string());
+                    return Ok(report);
+                }
+                Some(AuthResult::Failure { remaining_methods, partial_success }) => {
+                    if partial_success {
+                        report.methods.push(<&str>::from(&method).to_string());
+                        tried.clear();
+                        tried.push(method);
+                    }
+                    remaining = remaining_methods;
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Offers identities in order. Keys whose public half the agent holds are
+    // signed there, so only keys the agent lacks are decrypted locally.
+    async fn publickey<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+        report: &mut AuthReport,
+    ) -> anyhow::Result<Option<AuthResult>> {
+        let mut agent: Option<AgentKeys> = None;
+        if let Some(agent_path) = &self.agent_path {
+            match AgentKeys::connect(agent_path).await {
+                Ok(keys) => agent = Some(keys),
+                Err(e) => report.record("agent".to_string(), Err(format!("Agent: {}", e))),
+            }
+        }
+
+        let mut offered: Vec<PublicKey> = Vec::new();
+        let mut offered_certs: Vec<Certificate> = Vec::new();
+        let mut last = None;
+
+        for identity in &self.identities {
+            if handle.is_closed() {
+                break;
+            }
+
+            let label = identity.label();
+            let outcome = if identity.agent == Some(true) || (identity.key_path.is_none() && identity.agent_fingerprint.is_none()) {
+                let Some(agent) = agent.as_mut() else { continue };
+                let pending: Vec<AgentIdentity> = agent
+                    .identities
+                    .iter()
+                    .filter(|identity| match identity {
+                        AgentIdentity::Key(key) => {
+                            !matches!(key.algorithm(), Algorithm::Other(_))
+                                && !offered.iter().any(|o| o.key_data() == key.key_data())
+                        }
+                        AgentIdentity::Certificate { cert, .. } => {
+                            !offered_certs.iter().any(|o| o.signature() == cert.signature())
+                        }
+                    })
+                    .cloned()
+                    .collect();
+                for identity in pending {
+                    let (label, result) = match identity {
+                        AgentIdentity::Key(key) => {
+                            offered.push(key.clone());
+                            let label = format!("agent {} {}", key.fingerprint(HashAlg::Sha256), key.comment());
+                            (label, self.agent_sign(handle, &mut agent.agent, key).await)
+                        }
+                        AgentIdentity::Certificate { cert, comment } => {
+                            offered_certs.push(Certificate::clone(&cert));
+                            let label = format!("agent certificate {}", comment);
+                            (label, self.agent_sign_certificate(handle, &mut agent.agent, *cert).await)
+                        }
+                    };
+                    let accepted = matches!(&result, Ok(r) if !is_rejection(r));
+                    report.record(label, result.as_ref().map_err(|e| e.to_string()));
+                    if let Ok(result) = result {
+                        if accepted {
+                            return Ok(Some(result));
+                        }
+                        last = Some(result);
+                    }
+                    if handle.is_closed() {
+                        break;
+                    }
+                }
+                continue;
+            } else if let (None, Some(fingerprint)) = (&identity.key_path, &identity.agent_fingerprint) {
+                match agent.as_mut().and_then(|a| a.find(|k| same_fingerprint(k, fingerprint)).map(|k| (a, k))) {
+                    Some((agent, key)) => {
+                        offered.push(key.clone());
+                        self.agent_sign(handle, &mut agent.agent, key).await
+                    }
+                    None => Err(anyhow::anyhow!("key is not loaded in the agent")),
+                }
+            } else {
+                self.key_file(handle, identity, agent.as_mut(), &mut offered, &mut offered_certs).await
+            };
+
+            let accepted = matches!(&outcome, Ok(r) if !is_rejection(r));
+            report.record(label, outcome.as_ref().map_err(|e| e.to_string()));
+            if let Ok(result) = outcome {
+                if accepted {
+                    return Ok(Some(result));
+                }
+                last = Some(result);
+            }
+        }
+
+        Ok(last)
+    }
+
+    async fn key_file<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+        identity: &Identity,
+        agent: Option<&mut AgentKeys>,
+        offered: &mut Vec<PublicKey>,
+        offered_certs: &mut Vec<Certificate>,
+    ) -> anyhow::Result<AuthResult> {
+        let key_path = identity.key_path.as_deref().unwrap_or_default();
+
+        let cert = match &identity.cert_path {
+            Some(cert_path) => Some(
+                russh::keys::load_openssh_certificate(cert_path)
+                    .map_err(|e| anyhow::anyhow!("Cert load: {}", e))?,
+            ),
+            None => None,
+        };
+
+        if let Some(cert) = &cert {
+            let key = PublicKey::from(cert.public_key().clone());
+            if let Some(agent) = agent.filter(|a| a.holds(&key)) {
+                offered_certs.push(cert.clone());
+                return self.agent_sign_certificate(handle, &mut agent.agent, cert.clone()).await;
+            }
+            if !std::path::Path::new(key_path).exists() {
+                anyhow::bail!("certificate key is neither on disk nor in the agent");
+            }
+        } else {
+            let public = russh::keys::load_public_key(format!("{}.pub", key_path)).ok();
+            let held = agent.and_then(|a| {
+                a.find(|k| match (&public, &identity.agent_fingerprint) {
+                    (Some(public), _) => k.key_data() == public.key_data(),
+                    (None, Some(fingerprint)) => same_fingerprint(k, fingerprint),
+                    (None, None) => false,
+                })
+                .map(|k| (a, k))
+            });
+            if let Some((agent, key)) = held {
+                offered.push(key.clone());
+                return self.agent_sign(handle, &mut agent.agent, key).await;
+            }
+        }
+
+        let key = identity::load_private_key(key_path, self.passphrase.as_deref(), self.on_passphrase.as_ref())
+            .await
+            .map_err(|e| anyhow::anyhow!("Key load: {}", e))?;
+        offered.push(key.public_key().clone());
+
+        let result = match cert {
+            Some(cert) => {
+                offered_certs.push(cert.clone());
+                handle
+                    .authenticate_openssh_cert(self.username.as_str(), key, cert)
+                    .await?
+            }
+            None => {
+                let hash_alg = rsa_hash(handle, key.public_key()).await?;
+                handle
+                    .authenticate_publickey(self.username.as_str(), PrivateKeyWithHashAlg::new(key, hash_alg))
+                    .await?
+            }
+        };
+        Ok(result)
+    }
+
+    async fn agent_sign<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+        agent: &mut Agent,
+        key: PublicKey,
+    ) -> anyhow::Result<AuthResult> {
+        let hash_alg = rsa_hash(handle, &key).await?;
+        Ok(handle
+            .authenticate_publickey_with(self.username.as_str(), key, hash_alg, &mut AgentSigner(agent))
+            .await?)
+    }
+
+    // The agent signs with the certificate's plain key; OpenSSH agents accept
+    // that whether they were given the key alone or with the certificate.
+    async fn agent_sign_certificate<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+        agent: &mut Agent,
+        cert: Certificate,
+    ) -> anyhow::Result<AuthResult> {
+        let hash_alg = rsa_hash(handle, &PublicKey::from(cert.public_key().clone())).await?;
+        Ok(handle
+            .authenticate_certificate_with(self.username.as_str(), cert, hash_alg, &mut AgentSigner(agent))
+            .await?)
+    }
+
+    async fn password<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+    ) -> anyhow::Result<Option<AuthResult>> {
+        let mut last = None;
+
+        if let Some(password) = &self.password {
+            let result = handle
+                .authenticate_password(self.username.as_str(), password.as_str())
+                .await?;
+            if !is_rejection(&result) {
+                return Ok(Some(result));
+            }
+            last = Some(result);
+        }
+
+        if let Some(on_password) = &self.on_password {
+            for attempt in 1..=PASSWORD_ATTEMPTS {
+                let prompt = PasswordPrompt {
+                    username: self.username.clone(),
+                    host: self.host.clone(),
+                    attempt,
+                };
+                let Some(password): Option<String> = callback::ask(on_password, prompt).await? else {
+                    break;
+                };
+                let result = handle
+                    .authenticate_password(self.username.as_str(), password)
+                    .await?;
+                if !is_rejection(&result) {
+                    return Ok(Some(result));
+                }
+                last = Some(result);
+            }
+        }
+
+        Ok(last)
+    }
+
+    async fn keyboard_interactive<H: client::Handler>(
+        &self,
+        handle: &mut client::Handle<H>,
+    ) -> anyhow::Result<Option<AuthResult>> {
+        if self.on_keyboard_interactive.is_none() && self.password.is_none() {
+            return Ok(None);
+        }
+
+        let mut response = handle
+            .authenticate_keyboard_interactive_start(self.username.as_str(), None)
+            .await?;
+
+        loop {
+            match response {
+                KeyboardInteractiveAuthResponse::Success => return Ok(Some(AuthResult::Success)),
+                KeyboardInteractiveAuthResponse::Failure { remaining_methods, partial_success } => {
+                    return Ok(Some(AuthResult::Failure { remaining_methods, partial_success }));
+                }
+                KeyboardInteractiveAuthResponse::InfoRequest { name, instructions, prompts } => {
+                    let answers = self.answer_prompts(name, instructions, prompts).await?;
+                    response = handle
+                        .authenticate_keyboard_interactive_respond(answers)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    async fn answer_prompts(
+        &self,
+        name: String,
+        instructions: String,
+        prompts: Vec<client::Prompt>,
+    ) -> anyhow::Result<Vec<String>> {
+        if prompts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Without a handler, a lone hidden prompt is the server asking for the password.
+        let Some(on_keyboard_interactive) = &self.on_keyboard_interactive else {
+            return Ok(match (&self.password, prompts.as_slice()) {
+                (Some(password), [prompt]) if !prompt.echo => vec![password.clone()],
+                _ => vec![String::new(); prompts.len()],
+            });
+        };
+
+        let count = prompts.len();
+        let prompt = KeyboardInteractivePrompt {
+            username: self.username.clone(),
+            host: self.host.clone(),
+            name,
+            instructions,
+            prompts: prompts
+                .into_iter()
+                .map(|p| KeyboardInteractiveQuestion { prompt: p.prompt, echo: p.echo })
+                .collect(),
+        };
+        let answers: Option<Vec<String>> = callback::ask(on_keyboard_interactive, prompt).await?;
+
+        let mut answers = answers.unwrap_or_default();
+        answers.resize(count, String::new());
+        Ok(answers)
+    }
+}
@@ -0,0 +1,21 @@
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
+
+pub type Callback<T> = ThreadsafeFunction<T, ErrorStrategy::Fatal>;
+
+// Handlers whose answer is awaited. They are called Node-style, with `null`
+// before the value, so that one that throws or answers with the wrong type
+// fails the call rather than the process.
+pub type Prompt<T> = ThreadsafeFunction<T, ErrorStrategy::CalleeHandled>;
+
+// JS handlers may answer directly or through a Promise; both are awaited here.
+pub async fn ask<T, R>(prompt: &Prompt<T>, value: T) -> Result<R>
+where
+    T: 'static,
+    R: FromNapiValue + ValidateNapiValue + TypeName + Send + 'static,
+{
+    match prompt.call_async::<Either<Promise<R>, R>>(Ok(value)).await? {
+        Either::A(promise) => promise.await,
+        Either::B(answer) => Ok(answer),
+    }
+}
@@ -0,0 +1,56 @@
+use napi_derive::napi;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::watch;
+
+// Tokens for long-running calls (exec, transfers), each used by one call and
+// dropped when it finishes. Once cancelled a token carries a detail string,
+// such as the signal an exec should send; it is empty when none was given.
+static TOKENS: Lazy<Mutex<HashMap<u32, watch::Sender<Option<String>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_CANCEL_ID: AtomicU32 = AtomicU32::new(1);
+
+pub(crate) type Token = watch::Receiver<Option<String>>;
+
+#[napi]
+pub fn ssh_create_cancel_token() -> u32 {
+    let id = NEXT_CANCEL_ID.fetch_add(1, Ordering::Relaxed);
+    TOKENS.lock().insert(id, watch::channel(None).0);
+    id
+}
+
+// Cancelling a finished or unknown call is a no-op, like aborting a settled fetch.
+#[napi]
+pub fn ssh_cancel(cancel_id: u32) {
+    cancel(cancel_id, String::new());
+}
+
+pub(crate) fn cancel(cancel_id: u32, detail: String) {
+    if let Some(token) = TOKENS.lock().get(&cancel_id) {
+        token.send_replace(Some(detail));
+    }
+}
+
+pub(crate) fn subscribe(cancel_id: Option<u32>) -> Option<Token> {
+    TOKENS.lock().get(&cancel_id?).map(|token| token.subscribe())
+}
+
+pub(crate) fn release(cancel_id: Option<u32>) {
+    if let Some(id) = cancel_id {
+        TOKENS.lock().remove(&id);
+    }
+}
+
+// Resolves with the detail once cancelled; without a token it never resolves.
+pub(crate) async fn cancelled(token: Option<&mut Token>) -> String {
+    if let Some(token) = token {
+        let detail = token.wait_for(Option::is_some).await.map(|detail| detail.clone());
+        if let Ok(Some(detail)) = detail {
+            return detail;
+        }
+    }
+    std::future::pending().await
+}
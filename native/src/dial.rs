@@ -0,0 +1,268 @@
+use crate::ConnectOptions;
+use napi::bindgen_prelude::*;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::task::JoinSet;
+
+// Head start given to each address before the next one is tried, as the
+// Connection Attempt Delay of RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// Whatever carries a hop's SSH connection: a TCP socket, a channel through
+// the previous hop or a ProxyCommand's pipes.
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Family {
+    Any,
+    Inet,
+    Inet6,
+}
+
+// How the TCP connection of a direct hop is made, from AddressFamily,
+// BindAddress, BindInterface and the connect timeout.
+pub(crate) struct Dialer {
+    family: Family,
+    bind_address: Option<IpAddr>,
+    bind_interface: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl Dialer {
+    pub fn new(options: &ConnectOptions) -> Result<Dialer> {
+        let family = match options.address_family.as_deref() {
+            None | Some("any") => Family::Any,
+            Some("inet") => Family::Inet,
+            Some("inet6") => Family::Inet6,
+            Some(other) => {
+                return Err(napi::Error::new(
+                    Status::InvalidArg,
+                    format!("AddressFamily: expected inet, inet6 or any, got {}", other),
+                ))
+            }
+        };
+        let bind_address = options.bind_address.as_deref()
+            .map(|address| address.parse::<IpAddr>())
+            .transpose()
+            .map_err(|e| napi::Error::new(Status::InvalidArg, format!("BindAddress: {}", e)))?;
+
+        Ok(Dialer {
+            family,
+            bind_address,
+            bind_interface: options.bind_interface.clone(),
+            timeout: timeout(options.connect_timeout_ms),
+        })
+    }
+
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let Some(timeout) = self.timeout else {
+            return self.race(host, port).await;
+        };
+        tokio::time::timeout(timeout, self.race(host, port)).await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {} ms", timeout.as_millis()),
+            ))
+        })
+    }
+
+    // Tries every resolved address, alternating families and starting the next
+    // attempt whenever the previous one fails or has had ATTEMPT_DELAY without
+    // an answer. The first to connect wins and the rest are dropped.
+    async fn race(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut addresses = tokio::net::lookup_host((host, port)).await?
+            .filter(|address| self.allows(address))
+            .collect::<Vec<_>>();
+        addresses.dedup();
+        let mut addresses = interleave(addresses).into_iter().peekable();
+
+        let mut attempts = JoinSet::new();
+        let mut last_error = None;
+        loop {
+            if attempts.is_empty() {
+                match addresses.next() {
+                    Some(address) => {
+                        attempts.spawn(connect_one(address, self.bind_address, self.bind_interface.clone()));
+                    }
+                    None => {
+                        return Err(last_error.unwrap_or_else(|| {
+                            io::Error::new(io::ErrorKind::NotFound, format!("no usable address for {}", host))
+                        }))
+                    }
+                }
+            }
+
+            tokio::select! {
+                Some(done) = attempts.join_next() => match done {
+                    Ok(Ok(stream)) => return Ok(stream),
+                    Ok(Err(e)) => last_error = Some(e),
+                    Err(e) => last_error = Some(io::Error::other(e)),
+                },
+                _ = tokio::time::sleep(ATTEMPT_DELAY), if addresses.peek().is_some() => {
+                    if let Some(address) = addresses.next() {
+                        attempts.spawn(connect_one(address, self.bind_address, self.bind_interface.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    fn allows(&self, address: &SocketAddr) -> bool {
+        let family = match self.bind_address {
+            // A socket bound to an address can only reach its own family.
+            Some(IpAddr::V4(_)) => Family::Inet,
+            Some(IpAddr::V6(_)) => Family::Inet6,
+            None => self.family,
+        };
+        match family {
+            Family::Any => true,
+            Family::Inet => address.is_ipv4(),
+            Family::Inet6 => address.is_ipv6(),
+        }
+    }
+}
+
+pub(crate) fn timeout(ms: Option<u32>) -> Option<Duration> {
+    ms.filter(|&ms| ms > 0).map(|ms| Duration::from_millis(ms as u64))
+}
+
+// Orders addresses as RFC 8305 does: the family of the first answer first,
+// then alternating.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else { return addresses };
+    let ipv6_first = first.is_ipv6();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) =
+        addresses.into_iter().partition(|address| address.is_ipv6() == ipv6_first);
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop_front());
+        ordered.extend(other.pop_front());
+    }
+    ordered
+}
+
+async fn connect_one(address: SocketAddr, bind_address: Option<IpAddr>, bind_interface: Option<String>) -> io::Result<TcpStream> {
+    let socket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    if let Some(interface) = &bind_interface {
+        bind_device(&socket, interface)?;
+    }
+    if let Some(ip) = bind_address {
+        socket.bind(SocketAddr::new(ip, 0))?;
+    }
+    socket.connect(address).await
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", address, e)))
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+        .map_err(|e| io::Error::new(e.kind(), format!("BindInterface {}: {}", interface, e)))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &TcpSocket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "BindInterface is not supported on this platform"))
+}
+
+// Host key prompts raised during a handshake. `open` counts those still
+// waiting for an answer and `opened` every one so far.
+#[derive(Default)]
+pub(crate) struct Prompts {
+    open: AtomicU32,
+    opened: AtomicU32,
+}
+
+impl Prompts {
+    pub fn open(&self) -> Prompt<'_> {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        Prompt(self)
+    }
+}
+
+// Held while a prompt waits; dropping it, answered or not, closes the prompt.
+pub(crate) struct Prompt<'a>(&'a Prompts);
+
+impl Drop for Prompt<'_> {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Bounds the SSH handshake, up to the end of the first key exchange;
+// authentication is not included. Time spent on host key prompts does not
+// count: the timeout waits while one is open and starts over after it.
+pub(crate) async fn handshake<F: Future>(future: F, timeout: Option<Duration>, prompts: &Prompts) -> Option<F::Output> {
+    let Some(timeout) = timeout else {
+        return Some(future.await);
+    };
+    tokio::pin!(future);
+    let mut seen = prompts.opened.load(Ordering::Relaxed);
+    loop {
+        tokio::select! {
+            output = &mut future => return Some(output),
+            _ = tokio::time::sleep(timeout) => {
+                let opened = prompts.opened.load(Ordering::Relaxed);
+                if opened == seen && prompts.open.load(Ordering::Relaxed) == 0 {
+                    return None;
+                }
+                seen = opened;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_without_prompts() {
+        let prompts = Prompts::default();
+        let output = handshake(tokio::time::sleep(TIMEOUT * 2), Some(TIMEOUT), &prompts).await;
+        assert!(output.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_while_a_prompt_is_open() {
+        let prompts = Prompts::default();
+        let prompt = prompts.open();
+        let output = handshake(tokio::time::sleep(TIMEOUT * 5), Some(TIMEOUT), &prompts).await;
+        assert!(output.is_some());
+        drop(prompt);
+        assert_eq!(prompts.open.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starts_over_after_a_prompt() {
+        let prompts = Prompts::default();
+        let future = async {
+            // Answered within the first period; the handshake then needs
+            // most of a fresh one.
+            tokio::time::sleep(TIMEOUT / 2).await;
+            drop(prompts.open());
+            tokio::time::sleep(TIMEOUT).await;
+        };
+        assert!(handshake(future, Some(TIMEOUT), &prompts).await.is_some());
+    }
+
+    #[test]
+    fn interleaves_address_families() {
+        let v4 = |last| SocketAddr::from(([192, 0, 2, last], 22));
+        let v6 = |last| SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, last], 22));
+        assert_eq!(interleave(vec![v6(1), v6(2), v6(3), v4(1)]), vec![v6(1), v4(1), v6(2), v6(3)]);
+        assert_eq!(interleave(vec![v4(1), v4(2), v6(1), v6(2)]), vec![v4(1), v6(1), v4(2), v6(2)]);
+        assert!(interleave(Vec::new()).is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::callback::Callback;
+use crate::cancel;
+use crate::{get_session, Session};
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use russh::{client, Channel, ChannelMsg, Sig};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+const STDERR: u32 = 1;
+const DEFAULT_CANCEL_SIGNAL: &str = "TERM";
+pub(crate) const DEFAULT_TERM: &str = "xterm-256color";
+pub(crate) const DEFAULT_COLS: u32 = 80;
+pub(crate) const DEFAULT_ROWS: u32 = 24;
+
+#[napi(object)]
+pub struct ExecResult {
+    pub stdout: Buffer,
+    pub stderr: Buffer,
+    /// Missing when the process was killed by a signal or the server never reported it.
+    pub exit_code: Option<u32>,
+    pub exit_signal: Option<String>,
+    pub core_dumped: bool,
+    pub error_message: Option<String>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ExecExit {
+    pub exit_code: Option<u32>,
+    pub exit_signal: Option<String>,
+    pub core_dumped: bool,
+    pub error_message: Option<String>,
+}
+
+pub(crate) enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+pub(crate) fn signal_name(signal: &Sig) -> String {
+    match signal {
+        Sig::Custom(name) => name.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+pub(crate) fn signal_from_name(name: &str) -> Sig {
+    let name = name.trim().trim_start_matches("SIG").to_ascii_uppercase();
+    match name.as_str() {
+        "ABRT" => Sig::ABRT,
+        "ALRM" => Sig::ALRM,
+        "FPE" => Sig::FPE,
+        "HUP" => Sig::HUP,
+        "ILL" => Sig::ILL,
+        "INT" => Sig::INT,
+        "KILL" => Sig::KILL,
+        "PIPE" => Sig::PIPE,
+        "QUIT" => Sig::QUIT,
+        "SEGV" => Sig::SEGV,
+        "TERM" => Sig::TERM,
+        "USR1" => Sig::USR1,
+        _ => Sig::Custom(name),
+    }
+}
+
+// Counts a command as running in session info while it lives.
+struct Running<'a>(&'a Session);
+
+impl<'a> Running<'a> {
+    fn start(session: &'a Session) -> Self {
+        session.execs.fetch_add(1, Ordering::Relaxed);
+        Running(session)
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.execs.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) async fn open(session: &Session) -> Result<Channel<client::Msg>> {
+    let channel = session.handle.read().await.channel_open_session().await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Channel: {}", e)))?;
+
+    if session.forward_agent {
+        channel.agent_forward(false).await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Agent forward: {}", e)))?;
+    }
+    Ok(channel)
+}
+
+// Applies one channel message to `exit`; returns false once the server has
+// closed the channel.
+pub(crate) fn read(msg: ChannelMsg, exit: &mut ExecExit, output: &mut impl FnMut(Output)) -> bool {
+    match msg {
+        ChannelMsg::Data { data } => output(Output::Stdout(data.to_vec())),
+        ChannelMsg::ExtendedData { data, ext: STDERR } => output(Output::Stderr(data.to_vec())),
+        ChannelMsg::ExitStatus { exit_status } => exit.exit_code = Some(exit_status),
+        ChannelMsg::ExitSignal { signal_name: signal, core_dumped, error_message, .. } => {
+            exit.exit_signal = Some(signal_name(&signal));
+            exit.core_dumped = core_dumped;
+            exit.error_message = Some(error_message).filter(|m| !m.is_empty());
+        }
+        ChannelMsg::Close => return false,
+        _ => {}
+    }
+    true
+}
+
+// Reads until the server closes the channel: the exit status may arrive after
+// EOF, so stopping at the first EOF can lose it.
+pub(crate) async fn wait(channel: &mut Channel<client::Msg>, mut output: impl FnMut(Output)) -> ExecExit {
+    let mut exit = ExecExit::default();
+    while let Some(msg) = channel.wait().await {
+        if !read(msg, &mut exit, &mut output) {
+            break;
+        }
+    }
+    exit
+}
+
+#[napi]
+pub async fn ssh_exec(session_id: u32, command: String) -> Result<ExecResult> {
+    let session = get_session(session_id)?;
+    let _running = Running::start(&session);
+    let mut channel = open(&session).await?;
+
+    channel.exec(true, command).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Exec: {}", e)))?;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let exit = wait(&mut channel, |output| match output {
+        Output::Stdout(data) => stdout.extend_from_slice(&data),
+        Output::Stderr(data) => stderr.extend_from_slice(&data),
+    })
+    .await;
+
+    Ok(ExecResult {
+        stdout: stdout.into(),
+        stderr: stderr.into(),
+        exit_code: exit.exit_code,
+        exit_signal: exit.exit_signal,
+        core_dumped: exit.core_dumped,
+        error_message: exit.error_message,
+    })
+}
+
+// Pushes output to JS as it arrives instead of buffering it.
+#[napi]
+pub async fn ssh_exec_stream(
+    session_id: u32,
+    command: String,
+    #[napi(ts_arg_type = "(chunk: Buffer) => void")] on_stdout: Callback<Buffer>,
+    #[napi(ts_arg_type = "(chunk: Buffer) => void")] on_stderr: Callback<Buffer>,
+) -> Result<ExecExit> {
+    let session = get_session(session_id)?;
+    let _running = Running::start(&session);
+    let mut channel = open(&session).await?;
+
+    channel.exec(true, command).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Exec: {}", e)))?;
+
+    Ok(wait(&mut channel, |output| {
+        let (callback, data) = match output {
+            Output::Stdout(data) => (&on_stdout, data),
+            Output::Stderr(data) => (&on_stderr, data),
+        };
+        callback.call(data.into(), ThreadsafeFunctionCallMode::NonBlocking);
+    })
+    .await)
+}
+
+#[napi(object, object_to_js = false)]
+#[derive(Default)]
+pub struct ExecOptions {
+    /// Written to the command's stdin, followed by EOF.
+    pub stdin: Option<Buffer>,
+    pub env: Option<HashMap<String, String>>,
+    /// On expiry the command gets `cancelSignal` and the channel is closed.
+    pub timeout_ms: Option<u32>,
+    pub pty: Option<bool>,
+    /// With `pty`; defaults to "xterm-256color".
+    pub term: Option<String>,
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    /// Token from `sshCreateCancelToken`, for `sshCancel` or `sshCancelExec`.
+    pub cancel_id: Option<u32>,
+    /// Signal name sent on timeout; defaults to "TERM".
+    pub cancel_signal: Option<String>,
+    #[napi(ts_type = "(chunk: Buffer) => void")]
+    pub on_stdout: Option<Callback<Buffer>>,
+    #[napi(ts_type = "(chunk: Buffer) => void")]
+    pub on_stderr: Option<Callback<Buffer>>,
+}
+
+// Like `sshCancel`, choosing the signal sent to the command.
+#[napi]
+pub fn ssh_cancel_exec(cancel_id: u32, signal: Option<String>) {
+    cancel::cancel(cancel_id, signal.unwrap_or_default());
+}
+
+#[napi]
+pub async fn ssh_exec_with_options(session_id: u32, command: String, options: Option<ExecOptions>) -> Result<ExecResult> {
+    let options = options.unwrap_or_default();
+    let cancel_id = options.cancel_id;
+    let mut token = cancel::subscribe(cancel_id);
+
+    let result = exec_with_options(session_id, command, options, token.as_mut()).await;
+
+    cancel::release(cancel_id);
+    result
+}
+
+async fn exec_with_options(
+    session_id: u32,
+    command: String,
+    options: ExecOptions,
+    token: Option<&mut cancel::Token>,
+) -> Result<ExecResult> {
+    let ExecOptions { stdin, env, timeout_ms, pty, term, cols, rows, cancel_signal, on_stdout, on_stderr, .. } = options;
+    let stdin = stdin.map(|stdin| stdin.to_vec());
+
+    let session = get_session(session_id)?;
+    let _running = Running::start(&session);
+    let mut channel = open(&session).await?;
+
+    for (name, value) in env.iter().flatten() {
+        channel.set_env(false, name.as_str(), value.as_str()).await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Env: {}", e)))?;
+    }
+
+    if pty.unwrap_or(false) {
+        let term = term.as_deref().unwrap_or(DEFAULT_TERM);
+        channel.request_pty(false, term, cols.unwrap_or(DEFAULT_COLS), rows.unwrap_or(DEFAULT_ROWS), 0, 0, &[]).await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("PTY: {}", e)))?;
+    }
+
+    channel.exec(true, command).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Exec: {}", e)))?;
+
+    // Feeding stdin from its own task keeps a command that writes before it
+    // reads from stalling on a full channel window.
+    let stdin = stdin.map(|stdin| {
+        let mut writer = channel.make_writer();
+        tokio::spawn(async move {
+            let _ = writer.write_all(&stdin).await;
+            let _ = writer.shutdown().await;
+        })
+    });
+
+    let timeout_signal = cancel_signal.unwrap_or_else(|| DEFAULT_CANCEL_SIGNAL.to_string());
+    let stop = async {
+        let timeout = async {
+            match timeout_ms {
+                Some(ms) => tokio::time::sleep(Duration::from_millis(ms as u64)).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = timeout => (timeout_signal, format!("timed out after {} ms", timeout_ms.unwrap_or_default())),
+            signal = cancel::cancelled(token) => (signal, "cancelled".to_string()),
+        }
+    };
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let outcome = tokio::select! {
+        exit = wait(&mut channel, |output| match output {
+            Output::Stdout(data) => {
+                if let Some(on_stdout) = &on_stdout {
+                    on_stdout.call(data.clone().into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+                stdout.extend_from_slice(&data);
+            }
+            Output::Stderr(data) => {
+                if let Some(on_stderr) = &on_stderr {
+                    on_stderr.call(data.clone().into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+                stderr.extend_from_slice(&data);
+            }
+        }) => Ok(exit),
+        stopped = stop => Err(stopped),
+    };
+
+    if let Some(stdin) = stdin {
+        stdin.abort();
+    }
+
+    match outcome {
+        Ok(exit) => Ok(ExecResult {
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+            exit_code: exit.exit_code,
+            exit_signal: exit.exit_signal,
+            core_dumped: exit.core_dumped,
+            error_message: exit.error_message,
+        }),
+        Err((signal, reason)) => {
+            let signal = if signal.is_empty() { DEFAULT_CANCEL_SIGNAL } else { &signal };
+            let _ = channel.signal(signal_from_name(signal)).await;
+            let _ = channel.close().await;
+            Err(napi::Error::new(Status::Cancelled, format!("Exec: {}", reason)))
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use crate::{get_session, proxy, socks, Session};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use russh::{client, Channel, ChannelMsg};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+static FORWARDS: Lazy<Mutex<HashMap<u32, Arc<Forward>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_FORWARD_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Local,
+    Dynamic,
+    Socket,
+    Remote,
+    RemoteSocket,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Local => "local",
+            Kind::Dynamic => "dynamic",
+            Kind::Socket => "socket",
+            Kind::Remote => "remote",
+            Kind::RemoteSocket => "remote-socket",
+        }
+    }
+}
+
+#[napi(object)]
+pub struct ForwardInfo {
+    pub forward_id: u32,
+    pub session_id: u32,
+    /// One of "local", "dynamic", "socket", "remote" or "remote-socket".
+    pub kind: String,
+    /// Where connections are accepted: a local or remote address, or a remote socket path.
+    pub listen_address: String,
+    pub listen_port: Option<u32>,
+    pub target: String,
+    pub active_connections: u32,
+    pub total_connections: u32,
+    /// Bytes read from the local side and sent over SSH.
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+}
+
+pub(crate) struct Forward {
+    id: u32,
+    session_id: u32,
+    kind: Kind,
+    listen_address: String,
+    listen_port: Option<u32>,
+    target: String,
+    active: AtomicU32,
+    total: AtomicU32,
+    sent: AtomicU64,
+    received: AtomicU64,
+    stop: watch::Sender<bool>,
+}
+
+impl Forward {
+    fn register(
+        session_id: u32,
+        kind: Kind,
+        listen_address: String,
+        listen_port: Option<u32>,
+        target: String,
+    ) -> Arc<Self> {
+        let forward = Self::new(session_id, kind, listen_address, listen_port, target);
+        FORWARDS.lock().insert(forward.id, forward.clone());
+        forward
+    }
+
+    // Not yet listed, for a remote forward whose target must be in place
+    // before the server is asked to listen.
+    fn new(
+        session_id: u32,
+        kind: Kind,
+        listen_address: String,
+        listen_port: Option<u32>,
+        target: String,
+    ) -> Arc<Self> {
+        Arc::new(Forward {
+            id: NEXT_FORWARD_ID.fetch_add(1, Ordering::Relaxed),
+            session_id,
+            kind,
+            listen_address,
+            listen_port,
+            target,
+            active: AtomicU32::new(0),
+            total: AtomicU32::new(0),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            stop: watch::channel(false).0,
+        })
+    }
+
+    fn stopped(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut stop = self.stop.subscribe();
+        async move {
+            let _ = stop.wait_for(|stopped| *stopped).await;
+        }
+    }
+
+    fn info(&self) -> ForwardInfo {
+        ForwardInfo {
+            forward_id: self.id,
+            session_id: self.session_id,
+            kind: self.kind.name().to_string(),
+            listen_address: self.listen_address.clone(),
+            listen_port: self.listen_port,
+            target: self.target.clone(),
+            active_connections: self.active.load(Ordering::Relaxed),
+            total_connections: self.total.load(Ordering::Relaxed),
+            bytes_sent: self.sent.load(Ordering::Relaxed) as i64,
+            bytes_received: self.received.load(Ordering::Relaxed) as i64,
+        }
+    }
+
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        channel: Channel<client::Msg>,
+    ) -> std::result::Result<(), BoxError> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let result = pump(Counted { inner: stream, forward: self }, channel, self.stopped()).await;
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+}
+
+// Counts the bytes a forwarded connection moves in each direction.
+struct Counted<'a, S> {
+    inner: S,
+    forward: &'a Forward,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<'_, S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.forward.sent.fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<'_, S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.forward.received.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LocalTarget {
+    host: String,
+    port: u16,
+    forward: Arc<Forward>,
+}
+
+// Local targets for server-side listeners, keyed by the address and port or
+// the socket path the server bound.
+#[derive(Default)]
+pub(crate) struct RemoteTargets {
+    pub tcpip: HashMap<(String, u32), LocalTarget>,
+    pub streamlocal: HashMap<String, LocalTarget>,
+}
+
+pub(crate) type RemoteForwards = Arc<Mutex<RemoteTargets>>;
+
+// Leaves the entry alone if another forward has since taken the key, as
+// concurrent requests for port 0 share one.
+fn remove_target(targets: &mut HashMap<(String, u32), LocalTarget>, key: &(String, u32), forward: &Forward) {
+    if targets.get(key).is_some_and(|target| target.forward.id == forward.id) {
+        targets.remove(key);
+    }
+}
+
+async fn bind(address: &str, port: u16) -> Result<(TcpListener, u16)> {
+    let listener = TcpListener::bind((address, port))
+        .await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Bind: {}", e)))?;
+
+    let actual_port = listener.local_addr()
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Port: {}", e)))?
+        .port();
+
+    Ok((listener, actual_port))
+}
+
+// Accepts connections until the forward is stopped; `open` turns each one
+// into a stream and the channel it should be pumped to. Failures go to the
+// session log.
+fn accept_loop<F, Fut>(listener: TcpListener, forward: Arc<Forward>, log: proxy::Log, open: F)
+where
+    F: Fn(TcpStream, SocketAddr) -> Fut + Send + 'static,
+    Fut: Future<Output = std::result::Result<(TcpStream, Channel<client::Msg>), BoxError>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let stopped = forward.stopped();
+        tokio::pin!(stopped);
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                accepted = listener.accept() => {
+                    let Ok((stream, addr)) = accepted else { continue };
+                    let (forward, log) = (forward.clone(), log.clone());
+                    let opening = open(stream, addr);
+                    tokio::spawn(async move {
+                        let result = tokio::select! {
+                            _ = forward.stopped() => return,
+                            opened = opening => opened,
+                        };
+                        let result = match result {
+                            Ok((stream, channel)) => forward.serve(stream, channel).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            proxy::push_log(&log, format!("Forward error: {}", e));
+                        }
+                    });
+                }
+            }
+        }
+    });
+}
+
+#[napi]
+pub async fn ssh_forward_port(
+    session_id: u32,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+) -> Result<ForwardInfo> {
+    let session = get_session(session_id)?;
+    let (listener, actual_port) = bind("127.0.0.1", local_port).await?;
+
+    let forward = Forward::register(
+        session_id,
+        Kind::Local,
+        "127.0.0.1".to_string(),
+        Some(actual_port as u32),
+        format!("{}:{}", remote_host, remote_port),
+    );
+    let info = forward.info();
+    accept_loop(listener, forward, session.log.clone(), move |stream, addr| {
+        open_direct_tcpip(session.clone(), stream, addr, remote_host.clone(), remote_port)
+    });
+
+    Ok(info)
+}
+
+#[napi]
+pub async fn ssh_dynamic_forward(session_id: u32, bind_addr: String, port: u16) -> Result<ForwardInfo> {
+    let session = get_session(session_id)?;
+    let (listener, actual_port) = bind(&bind_addr, port).await?;
+
+    let forward = Forward::register(session_id, Kind::Dynamic, bind_addr, Some(actual_port as u32), "socks".to_string());
+    let info = forward.info();
+    accept_loop(listener, forward, session.log.clone(), move |stream, addr| open_socks(session.clone(), stream, addr));
+
+    Ok(info)
+}
+
+#[napi]
+pub async fn ssh_forward_socket(session_id: u32, local_port: u16, remote_socket_path: String) -> Result<ForwardInfo> {
+    let session = get_session(session_id)?;
+    let (listener, actual_port) = bind("127.0.0.1", local_port).await?;
+
+    let forward = Forward::register(
+        session_id,
+        Kind::Socket,
+        "127.0.0.1".to_string(),
+        Some(actual_port as u32),
+        remote_socket_path.clone(),
+    );
+    let info = forward.info();
+    accept_loop(listener, forward, session.log.clone(), move |stream, _| {
+        open_direct_streamlocal(session.clone(), stream, remote_socket_path.clone())
+    });
+
+    Ok(info)
+}
+
+#[napi]
+pub async fn ssh_remote_forward(
+    session_id: u32,
+    remote_addr: String,
+    remote_port: u32,
+    local_host: String,
+    local_port: u16,
+) -> Result<ForwardInfo> {
+    let session = get_session(session_id)?;
+    let target = format!("{}:{}", local_host, local_port);
+
+    // The server may open channels as soon as it listens, before its reply
+    // arrives, so the target is in place first.
+    let requested = (remote_addr.clone(), remote_port);
+    let pending = Forward::new(session_id, Kind::Remote, remote_addr.clone(), Some(remote_port), target.clone());
+    {
+        let mut targets = session.remote_forwards.lock();
+        if remote_port != 0 && targets.tcpip.contains_key(&requested) {
+            return Err(napi::Error::new(
+                Status::GenericFailure,
+                format!("Remote forward: {}:{} is already forwarded", remote_addr, remote_port),
+            ));
+        }
+        let local = LocalTarget { host: local_host.clone(), port: local_port, forward: pending.clone() };
+        targets.tcpip.insert(requested.clone(), local);
+    }
+
+    let result = session.handle.write().await.tcpip_forward(remote_addr.clone(), remote_port).await;
+    let bound_port = match result {
+        Ok(bound_port) => bound_port,
+        Err(e) => {
+            remove_target(&mut session.remote_forwards.lock().tcpip, &requested, &pending);
+            return Err(napi::Error::new(Status::GenericFailure, format!("Remote forward: {}", e)));
+        }
+    };
+
+    // Only now is the port the server picked known.
+    let forward = if remote_port == 0 {
+        let forward = Forward::new(session_id, Kind::Remote, remote_addr.clone(), Some(bound_port), target);
+        let mut targets = session.remote_forwards.lock();
+        remove_target(&mut targets.tcpip, &requested, &pending);
+        let local = LocalTarget { host: local_host, port: local_port, forward: forward.clone() };
+        targets.tcpip.insert((remote_addr, bound_port), local);
+        forward
+    } else {
+        pending
+    };
+    FORWARDS.lock().insert(forward.id, forward.clone());
+    Ok(forward.info())
+}
+
+#[napi]
+pub async fn ssh_cancel_remote_forward(session_id: u32, remote_addr: String, remote_port: u32) -> Result<()> {
+    let session = get_session(session_id)?;
+    let target = session.remote_forwards.lock().tcpip.get(&(remote_addr, remote_port)).cloned();
+    match target {
+        Some(target) => close(&target.forward).await,
+        None => Err(napi::Error::new(Status::GenericFailure, "Invalid forward")),
+    }
+}
+
+#[napi]
+pub async fn ssh_remote_forward_socket(
+    session_id: u32,
+    remote_socket_path: String,
+    local_host: String,
+    local_port: u16,
+) -> Result<ForwardInfo> {
+    let session = get_session(session_id)?;
+
+    let forward = Forward::new(
+        session_id,
+        Kind::RemoteSocket,
+        remote_socket_path.clone(),
+        None,
+        format!("{}:{}", local_host, local_port),
+    );
+    {
+        let mut targets = session.remote_forwards.lock();
+        if targets.streamlocal.contains_key(&remote_socket_path) {
+            return Err(napi::Error::new(
+                Status::GenericFailure,
+                format!("Remote forward: {} is already forwarded", remote_socket_path),
+            ));
+        }
+        let local = LocalTarget { host: local_host, port: local_port, forward: forward.clone() };
+        targets.streamlocal.insert(remote_socket_path.clone(), local);
+    }
+
+    let result = session.handle.write().await.streamlocal_forward(remote_socket_path.clone()).await;
+    if let Err(e) = result {
+        session.remote_forwards.lock().streamlocal.remove(&remote_socket_path);
+        return Err(napi::Error::new(Status::GenericFailure, format!("Remote forward: {}", e)));
+    }
+
+    FORWARDS.lock().insert(forward.id, forward.clone());
+    Ok(forward.info())
+}
+
+#[napi]
+pub async fn ssh_cancel_remote_forward_socket(session_id: u32, remote_socket_path: String) -> Result<()> {
+    let session = get_session(session_id)?;
+    let target = session.remote_forwards.lock().streamlocal.get(&remote_socket_path).cloned();
+    match target {
+        Some(target) => close(&target.forward).await,
+        None => Err(napi::Error::new(Status::GenericFailure, "Invalid forward")),
+    }
+}
+
+#[napi]
+pub fn ssh_list_forwards(session_id: Option<u32>) -> Vec<ForwardInfo> {
+    let mut forwards: Vec<ForwardInfo> = FORWARDS.lock()
+        .values()
+        .filter(|f| session_id.is_none_or(|id| f.session_id == id))
+        .map(|f| f.info())
+        .collect();
+    forwards.sort_by_key(|f| f.forward_id);
+    forwards
+}
+
+#[napi]
+pub async fn ssh_close_forward(forward_id: u32) -> Result<()> {
+    let forward = FORWARDS.lock().get(&forward_id).cloned()
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Invalid forward"))?;
+    close(&forward).await
+}
+
+// Stops the listener and every connection it carries; remote listeners are
+// also cancelled on the server.
+async fn close(forward: &Forward) -> Result<()> {
+    stop(forward);
+
+    let Ok(session) = get_session(forward.session_id) else {
+        return Ok(());
+    };
+    let address = forward.listen_address.clone();
+    match forward.kind {
+        Kind::Remote => {
+            let port = forward.listen_port.unwrap_or_default();
+            session.remote_forwards.lock().tcpip.remove(&(address.clone(), port));
+            session.handle.read().await
+                .cancel_tcpip_forward(address, port)
+                .await
+                .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Cancel remote forward: {}", e)))?;
+        }
+        Kind::RemoteSocket => {
+            session.remote_forwards.lock().streamlocal.remove(&address);
+            session.handle.read().await
+                .cancel_streamlocal_forward(address)
+                .await
+                .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Cancel remote forward: {}", e)))?;
+        }
+        Kind::Local | Kind::Dynamic | Kind::Socket => {}
+    }
+    Ok(())
+}
+
+// Asks a reconnected session's server for its remote listeners again.
+// Forwards it refuses are closed; returns a line for each of them.
+pub(crate) async fn restore_remote(session: &Session) -> Vec<String> {
+    let (tcpip, streamlocal) = {
+        let targets = session.remote_forwards.lock();
+        let tcpip: Vec<_> = targets.tcpip.iter().map(|(key, target)| (key.clone(), target.forward.clone())).collect();
+        let streamlocal: Vec<_> = targets.streamlocal.iter().map(|(path, target)| (path.clone(), target.forward.clone())).collect();
+        (tcpip, streamlocal)
+    };
+
+    // One request per write lock, so channels can still open in between.
+    let mut failed = Vec::new();
+    for ((address, port), forward) in tcpip {
+        let result = session.handle.write().await.tcpip_forward(address.clone(), port).await;
+        if let Err(e) = result {
+            failed.push(format!("Remote forward {}:{}: {}", address, port, e));
+            session.remote_forwards.lock().tcpip.remove(&(address, port));
+            stop(&forward);
+        }
+    }
+    for (path, forward) in streamlocal {
+        let result = session.handle.write().await.streamlocal_forward(path.clone()).await;
+        if let Err(e) = result {
+            failed.push(format!("Remote forward {}: {}", path, e));
+            session.remote_forwards.lock().streamlocal.remove(&path);
+            stop(&forward);
+        }
+    }
+    failed
+}
+
+fn stop(forward: &Forward) {
+    FORWARDS.lock().remove(&forward.id);
+    forward.stop.send_replace(true);
+}
+
+// Stops every forward of a session that is going away.
+pub(crate) fn close_session(session_id: u32) {
+    FORWARDS.lock().retain(|_, forward| {
+        if forward.session_id != session_id {
+            return true;
+        }
+        forward.stop.send_replace(true);
+        false
+    });
+}
+
+async fn open_direct_tcpip(
+    session: Arc<Session>,
+    stream: TcpStream,
+    originator_addr: SocketAddr,
+    remote_host: String,
+    remote_port: u16,
+) -> std::result::Result<(TcpStream, Channel<client::Msg>), BoxError> {
+    let channel = session.handle.read().await
+        .channel_open_direct_tcpip(
+            remote_host,
+            remote_port as u32,
+            originator_addr.ip().to_string(),
+            originator_addr.port() as u32,
+        )
+        .await?;
+
+    Ok((stream, channel))
+}
+
+async fn open_direct_streamlocal(
+    session: Arc<Session>,
+    stream: TcpStream,
+    remote_socket_path: String,
+) -> std::result::Result<(TcpStream, Channel<client::Msg>), BoxError> {
+    let channel = session.handle.read().await
+        .channel_open_direct_streamlocal(remote_socket_path)
+        .await?;
+
+    Ok((stream, channel))
+}
+
+async fn open_socks(
+    session: Arc<Session>,
+    mut stream: TcpStream,
+    originator_addr: SocketAddr,
+) -> std::result::Result<(TcpStream, Channel<client::Msg>), BoxError> {
+    let target = socks::accept(&mut stream).await?;
+
+    let channel = session.handle.read().await
+        .channel_open_direct_tcpip(
+            target.host.clone(),
+            target.port as u32,
+            originator_addr.ip().to_string(),
+            originator_addr.port() as u32,
+        )
+        .await;
+
+    let channel = match channel {
+        Ok(channel) => channel,
+        Err(e) => {
+            let _ = socks::reply(&mut stream, &target, false).await;
+            return Err(format!("{}:{}: {}", target.host, target.port, e).into());
+        }
+    };
+    socks::reply(&mut stream, &target, true).await?;
+
+    Ok((stream, channel))
+}
+
+// Connects a channel the server opened for a remote forward to its local target.
+pub(crate) fn connect_back(channel: Channel<client::Msg>, target: Option<LocalTarget>, log: proxy::Log) {
+    tokio::spawn(async move {
+        let Some(target) = target else {
+            let _ = channel.close().await;
+            return;
+        };
+
+        match TcpStream::connect((target.host.as_str(), target.port)).await {
+            Ok(stream) => {
+                if let Err(e) = target.forward.serve(stream, channel).await {
+                    proxy::push_log(&log, format!("Remote forward error: {}", e));
+                }
+            }
+            Err(e) => {
+                proxy::push_log(&log, format!("Remote forward error: {}:{}: {}", target.host, target.port, e));
+                let _ = channel.close().await;
+            }
+        }
+    });
+}
+
+pub(crate) async fn pump<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    mut channel: Channel<client::Msg>,
+    stop: impl Future<Output = ()>,
+) -> std::result::Result<(), BoxError> {
+    let mut stream_closed = false;
+    let mut channel_closed = false;
+    let mut buf = vec![0; 65536];
+    tokio::pin!(stop);
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => {
+                let _ = channel.close().await;
+                break;
+            },
+            r = stream.read(&mut buf), if !stream_closed => {
+                match r {
+                    Ok(0) => {
+                        stream_closed = true;
+                        let _ = channel.eof().await;
+                        if channel_closed {
+                            break;
+                        }
+                    },
+                    Ok(n) => {
+                        if channel.data(&buf[..n]).await.is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            },
+            Some(msg) = channel.wait() => {
+                match msg {
+                    ChannelMsg::Data { ref data } if stream.write_all(data).await.is_err() => break,
+                    ChannelMsg::Data { .. } => {}
+                    ChannelMsg::Eof => {
+                        channel_closed = true;
+                        let _ = stream.shutdown().await;
+                        if stream_closed {
+                            break;
+                        }
+                    }
+                    ChannelMsg::ExitStatus { .. } => {
+                        channel_closed = true;
+                        if stream_closed {
+                            break;
+                        }
+                    }
+                    ChannelMsg::WindowAdjusted { .. } => {}
+                    _ => {}
+                }
+            },
+            else => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(port: u32) -> LocalTarget {
+        let forward = Forward::new(1, Kind::Remote, "0.0.0.0".to_string(), Some(port), "localhost:80".to_string());
+        LocalTarget { host: "localhost".to_string(), port: 80, forward }
+    }
+
+    #[test]
+    fn removes_only_its_own_target() {
+        let key = ("0.0.0.0".to_string(), 0);
+        let (first, second) = (target(0), target(0));
+        let mut targets = HashMap::new();
+        targets.insert(key.clone(), first.clone());
+        targets.insert(key.clone(), second.clone());
+
+        remove_target(&mut targets, &key, &first.forward);
+        assert!(targets.contains_key(&key));
+        remove_target(&mut targets, &key, &second.forward);
+        assert!(targets.is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+// Path globs for directory transfers, matched against '/'-separated paths
+// relative to the transfer root, and for algorithm names. `*` and `?` stop at
+// '/', `**` crosses it and `[a-z]` / `[!a-z]` match one character from a set.
+// A pattern without '/' matches the last component at any depth, as in
+// .gitignore.
+pub(crate) struct Pattern {
+    chars: Vec<char>,
+    anchored: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let pattern = pattern.trim_start_matches('/');
+        Pattern {
+            chars: pattern.trim_end_matches('/').chars().collect(),
+            anchored: pattern.contains('/'),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path = if self.anchored { path } else { path.rsplit('/').next().unwrap_or(path) };
+        match_from(&self.chars, &path.chars().collect::<Vec<_>>())
+    }
+}
+
+// Include patterns select files; exclude patterns drop files and prune whole
+// directories.
+pub(crate) struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Filter {
+        Filter {
+            include: include.iter().map(|p| Pattern::new(p)).collect(),
+            exclude: exclude.iter().map(|p| Pattern::new(p)).collect(),
+        }
+    }
+
+    pub fn file(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+
+    pub fn directory(&self, path: &str) -> bool {
+        !self.exclude.iter().any(|p| p.matches(path))
+    }
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "**/" also matches no directories at all.
+            if let ['/', after @ ..] = rest {
+                if match_from(after, path) {
+                    return true;
+                }
+            }
+            (0..=path.len()).any(|i| match_from(rest, &path[i..]))
+        }
+        ['*', rest @ ..] => {
+            for i in 0..=path.len() {
+                if match_from(rest, &path[i..]) {
+                    return true;
+                }
+                if path.get(i) == Some(&'/') {
+                    return false;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && match_from(rest, &path[1..]),
+        ['[', class @ ..] => match match_class(class, path.first().copied()) {
+            Some((matched, len)) => matched && match_from(&class[len..], &path[1..]),
+            // An unterminated set is a literal '['.
+            None => path.first() == Some(&'[') && match_from(class, &path[1..]),
+        },
+        [c, rest @ ..] => path.first() == Some(c) && match_from(rest, &path[1..]),
+    }
+}
+
+// Returns whether `c` is in the set and how much of the pattern the set used,
+// closing ']' included; None when there is no closing ']'.
+fn match_class(class: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!' | '^'));
+    let mut i = negated as usize;
+    let mut matched = false;
+    // A ']' straight after the opening bracket is part of the set.
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            let matched = c.is_some_and(|c| c != '/' && matched != negated);
+            return Some((matched, i + 1));
+        }
+        first = false;
+        if let (Some('-'), Some(&high)) = (class.get(i + 1), class.get(i + 2)) {
+            if high != ']' {
+                matched |= c.is_some_and(|c| class[i] <= c && c <= high);
+                i += 3;
+                continue;
+            }
+        }
+        matched |= c == Some(class[i]);
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Pattern::new(pattern).matches(path)
+    }
+
+    // The files a transfer would visit, walking directories as sync does:
+    // a pruned directory hides everything below it.
+    fn walk<'a>(filter: &Filter, files: &[&'a str]) -> Vec<&'a str> {
+        files.iter()
+            .filter(|path| {
+                let parts: Vec<_> = path.split('/').collect();
+                (1..parts.len()).all(|i| filter.directory(&parts[..i].join("/"))) && filter.file(path)
+            })
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn star_and_question_mark_stop_at_slash() {
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/a/lib.rs"));
+        assert!(matches("src/?.rs", "src/a.rs"));
+        assert!(!matches("src?lib.rs", "src/lib.rs"));
+        assert!(matches("a*", "a"));
+    }
+
+    #[test]
+    fn double_star_crosses_slashes() {
+        assert!(matches("src/**/*.rs", "src/lib.rs"));
+        assert!(matches("src/**/*.rs", "src/a/b/lib.rs"));
+        assert!(matches("src/**", "src/a/b"));
+        assert!(matches("**/test", "a/b/test"));
+        assert!(matches("**/test", "test"));
+        assert!(!matches("src/**/*.rs", "lib/lib.rs"));
+    }
+
+    #[test]
+    fn sets_and_ranges() {
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[0-9].txt", "filex.txt"));
+        assert!(matches("file[!0-9].txt", "filex.txt"));
+        assert!(matches("file[^0-9].txt", "filex.txt"));
+        assert!(!matches("file[!0-9].txt", "file7.txt"));
+        assert!(matches("[]a].txt", "].txt"));
+        assert!(matches("[a-].txt", "-.txt"));
+        assert!(!matches("a[!b]c", "a/c"));
+        // An unterminated set is literal.
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn slashless_patterns_match_the_last_component() {
+        assert!(matches("*.log", "debug.log"));
+        assert!(matches("*.log", "a/b/debug.log"));
+        assert!(matches("build", "a/build"));
+        assert!(!matches("a/*.log", "b/a/debug.log"));
+        // A leading or trailing '/' is dropped, and the leading one anchors.
+        assert!(matches("/build/", "build"));
+        assert!(!matches("/a/build", "x/a/build"));
+    }
+
+    #[test]
+    fn include_selects_files_and_exclude_wins() {
+        let filter = Filter::new(&["*.rs".to_string()], &["generated_*".to_string()]);
+        assert!(filter.file("src/lib.rs"));
+        assert!(!filter.file("src/lib.ts"));
+        assert!(!filter.file("src/generated_api.rs"));
+        assert!(Filter::new(&[], &[]).file("anything"));
+        // Include patterns never prune directories.
+        assert!(filter.directory("src"));
+    }
+
+    #[test]
+    fn exclude_prunes_directories() {
+        let filter = Filter::new(&[], &["node_modules".to_string(), "out/cache".to_string()]);
+        let files = [
+            "index.js",
+            "node_modules/a/index.js",
+            "packages/x/node_modules/b.js",
+            "out/main.js",
+            "out/cache/blob",
+            "out/cache/deep/blob",
+        ];
+        assert_eq!(walk(&filter, &files), ["index.js", "out/main.js"]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::callback::{self, Prompt};
+use crate::PassphrasePrompt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use russh::keys::PrivateKey;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const PASSPHRASE_ATTEMPTS: u32 = 3;
+
+// Decrypted keys outlive the session that unlocked them so reconnects and
+// jump hosts do not prompt again.
+static DECRYPTED_KEYS: Lazy<Mutex<HashMap<PathBuf, Arc<PrivateKey>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn cache_key(key_path: &str) -> PathBuf {
+    let path = crate::known_hosts::expand_path(key_path);
+    std::fs::canonicalize(&path).unwrap_or(path)
+}
+
+pub async fn load_private_key(
+    key_path: &str,
+    passphrase: Option<&str>,
+    on_passphrase: Option<&Prompt<PassphrasePrompt>>,
+) -> anyhow::Result<Arc<PrivateKey>> {
+    let cache_key = cache_key(key_path);
+    if let Some(key) = DECRYPTED_KEYS.lock().get(&cache_key) {
+        return Ok(key.clone());
+    }
+
+    let encoded = tokio::fs::read_to_string(&cache_key).await?;
+    let key = match russh::keys::decode_secret_key(&encoded, None) {
+        Ok(key) => key,
+        Err(russh::keys::Error::KeyIsEncrypted) => decrypt(&encoded, key_path, passphrase, on_passphrase).await?,
+        Err(e) => return Err(e.into()),
+    };
+
+    let key = Arc::new(key);
+    DECRYPTED_KEYS.lock().insert(cache_key, key.clone());
+    Ok(key)
+}
+
+async fn decrypt(
+    encoded: &str,
+    key_path: &str,
+    passphrase: Option<&str>,
+    on_passphrase: Option<&Prompt<PassphrasePrompt>>,
+) -> anyhow::Result<PrivateKey> {
+    let mut last_error = None;
+
+    if let Some(passphrase) = passphrase {
+        match russh::keys::decode_secret_key(encoded, Some(passphrase)) {
+            Ok(key) => return Ok(key),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    if let Some(on_passphrase) = on_passphrase {
+        for attempt in 1..=PASSPHRASE_ATTEMPTS {
+            let prompt = PassphrasePrompt {
+                key_path: key_path.to_string(),
+                attempt,
+            };
+            let Some(passphrase): Option<String> = callback::ask(on_passphrase, prompt).await? else {
+                anyhow::bail!("passphrase entry cancelled for {}", key_path);
+            };
+            match russh::keys::decode_secret_key(encoded, Some(&passphrase)) {
+                Ok(key) => return Ok(key),
+                Err(e) => last_error = Some(e),
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(anyhow::anyhow!("could not decrypt {}: {}", key_path, e)),
+        None => Err(anyhow::anyhow!("{} is encrypted and no passphrase was provided", key_path)),
+    }
+}
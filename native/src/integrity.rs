@@ -0,0 +1,185 @@
+use crate::exec::{self, Output};
+use crate::sftp::Sftp;
+use crate::transfer::IntegrityMismatch;
+use crate::Session;
+use data_encoding::HEXLOWER;
+use napi::bindgen_prelude::*;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, Packet};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+// Servers advertise "check-file" and answer either request.
+const CHECK_FILE: &str = "check-file";
+const CHECK_FILE_NAME: &str = "check-file-name";
+const CHECK_FILE_HANDLE: &str = "check-file-handle";
+const HASH_BUFFER: usize = 256 * 1024;
+
+pub(crate) fn hex(hasher: Sha256) -> String {
+    HEXLOWER.encode(&hasher.finalize())
+}
+
+// Feeds the next `len` bytes of `file` to `hasher`, for the part of a resumed
+// transfer that was already in place.
+pub(crate) async fn hash_prefix(file: &mut tokio::fs::File, len: u64, hasher: &mut Sha256) -> std::io::Result<()> {
+    let mut buffer = vec![0; HASH_BUFFER];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = remaining.min(HASH_BUFFER as u64) as usize;
+        file.read_exact(&mut buffer[..want]).await?;
+        hasher.update(&buffer[..want]);
+        remaining -= want as u64;
+    }
+    Ok(())
+}
+
+pub(crate) async fn file_sha256(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let mut hasher = Sha256::new();
+    hash_prefix(&mut file, len, &mut hasher).await?;
+    Ok(hex(hasher))
+}
+
+// Transfers report a mismatch in their result; callers that can only fail,
+// such as sync, turn it into this error.
+pub(crate) fn mismatch(path: &str, mismatch: &IntegrityMismatch) -> napi::Error {
+    napi::Error::new(
+        Status::GenericFailure,
+        format!("Integrity mismatch: {}: local sha256 {}, remote sha256 {}", path, mismatch.local_sha256, mismatch.remote_sha256),
+    )
+}
+
+pub(crate) fn compare(local: String, remote: String) -> std::result::Result<String, IntegrityMismatch> {
+    if local == remote {
+        Ok(local)
+    } else {
+        Err(IntegrityMismatch { local_sha256: local, remote_sha256: remote })
+    }
+}
+
+// Returns the remote file's SHA-256 and how it was obtained: the check-file
+// SFTP extension where the server offers it, else `sha256sum` (or `shasum`
+// on BSD-like systems) run over an exec channel.
+pub(crate) async fn remote_sha256(session: &Session, sftp: &Sftp, path: &str) -> Result<(String, &'static str)> {
+    if sftp.supports(CHECK_FILE) || sftp.supports(CHECK_FILE_NAME) {
+        if let Some(hash) = check_file(sftp, path).await {
+            return Ok((hash, CHECK_FILE));
+        }
+    }
+    Ok((sha256sum(session, path).await?, "sha256sum"))
+}
+
+// check-file from draft-ietf-secsh-filexfer-extensions, by name and failing
+// that through an open handle.
+async fn check_file(sftp: &Sftp, path: &str) -> Option<String> {
+    if let Some(hash) = request_check_file(sftp, CHECK_FILE_NAME, path).await {
+        return Some(hash);
+    }
+    let handle = sftp.raw.open(path, OpenFlags::READ, FileAttributes::empty()).await.ok()?.handle;
+    let hash = request_check_file(sftp, CHECK_FILE_HANDLE, &handle).await;
+    let _ = sftp.raw.close(handle).await;
+    hash
+}
+
+// Hashes the whole file (offset 0, length 0) as a single block (block size 0).
+async fn request_check_file(sftp: &Sftp, extension: &str, target: &str) -> Option<String> {
+    let mut request = Vec::new();
+    for value in [target, "sha256"] {
+        request.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        request.extend_from_slice(value.as_bytes());
+    }
+    request.extend_from_slice(&0u64.to_be_bytes());
+    request.extend_from_slice(&0u64.to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes());
+
+    let Ok(Packet::ExtendedReply(reply)) = sftp.raw.extended(extension, request).await else {
+        return None;
+    };
+
+    let data = reply.data;
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let algorithm = data.get(4..4 + len)?;
+    let hash = data.get(4 + len..)?;
+    (algorithm == b"sha256" && hash.len() == 32).then(|| HEXLOWER.encode(hash))
+}
+
+async fn sha256sum(session: &Session, path: &str) -> Result<String> {
+    let path = shell_quote(path);
+    let command = format!(
+        "sha256sum -- {path} 2>/dev/null || shasum -a 256 -- {path}",
+        path = path
+    );
+
+    let mut channel = exec::open(session).await?;
+    channel.exec(true, command).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Exec: {}", e)))?;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let exit = exec::wait(&mut channel, |output| match output {
+        Output::Stdout(data) => stdout.extend_from_slice(&data),
+        Output::Stderr(data) => stderr.extend_from_slice(&data),
+    })
+    .await;
+
+    let stdout = String::from_utf8_lossy(&stdout);
+    let hash = stdout.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+    if exit.exit_code != Some(0) || hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(napi::Error::new(
+            Status::GenericFailure,
+            format!("Remote sha256: {}", String::from_utf8_lossy(&stderr).trim()),
+        ));
+    }
+    Ok(hash)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_reports_both_hashes_on_mismatch() {
+        assert_eq!(compare("ab".into(), "ab".into()), Ok("ab".to_string()));
+        assert_eq!(
+            compare("ab".into(), "cd".into()),
+            Err(IntegrityMismatch { local_sha256: "ab".into(), remote_sha256: "cd".into() })
+        );
+    }
+
+    #[tokio::test]
+    async fn hashes_streamed_prefixes() {
+        let path = std::env::temp_dir().join(format!("integrity-{}", std::process::id()));
+        let data: Vec<u8> = (0..HASH_BUFFER * 2 + 100).map(|i| i as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        // A prefix spanning more than one buffer, then the rest of the file.
+        let len = HASH_BUFFER + 10;
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        let mut hasher = Sha256::new();
+        hash_prefix(&mut file, len as u64, &mut hasher).await.unwrap();
+        assert_eq!(hex(hasher.clone()), HEXLOWER.encode(&Sha256::digest(&data[..len])));
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, data[len..]);
+
+        // Asking for more than is there fails rather than hashing short.
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        assert!(hash_prefix(&mut file, data.len() as u64 + 1, &mut Sha256::new()).await.is_err());
+
+        assert_eq!(file_sha256(&path).await.unwrap(), HEXLOWER.encode(&Sha256::digest(&data)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mismatch_error_names_the_file() {
+        let found = IntegrityMismatch { local_sha256: "ab".into(), remote_sha256: "cd".into() };
+        assert_eq!(
+            mismatch("/tmp/f", &found).reason,
+            "Integrity mismatch: /tmp/f: local sha256 ab, remote sha256 cd"
+        );
+    }
+}
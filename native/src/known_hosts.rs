@@ -0,0 +1,541 @@
+use data_encoding::BASE64;
+use hmac::{Hmac, Mac};
+use russh::keys::ssh_key::public::KeyData;
+use russh::keys::{EcdsaCurve, HashAlg, PublicKey};
+use sha1::Sha1;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const HASH_MAGIC: &str = "|1|";
+const HASH_DELIM: char = '|';
+const CHANGED_PREFIX: &str = "# host key changed: ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrictHostKeyChecking {
+    Yes,
+    Ask,
+    AcceptNew,
+    No,
+}
+
+impl StrictHostKeyChecking {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "yes" | "true" => Ok(Self::Yes),
+            "ask" => Ok(Self::Ask),
+            "accept-new" => Ok(Self::AcceptNew),
+            "no" | "off" | "false" => Ok(Self::No),
+            other => Err(format!("Unsupported StrictHostKeyChecking value: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+struct Entry {
+    marker: Option<Marker>,
+    hosts: String,
+    key: PublicKey,
+    path: PathBuf,
+    line: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    Known,
+    Unknown,
+    Changed { path: PathBuf, line: usize },
+    Revoked { path: PathBuf, line: usize },
+    // Unknown, but a `@cert-authority` line covers the host; certificate host
+    // keys are not supported, so it cannot vouch for the key.
+    CertAuthority { path: PathBuf, line: usize },
+}
+
+#[derive(Default)]
+pub struct KnownHosts {
+    entries: Vec<Entry>,
+}
+
+impl KnownHosts {
+    pub fn load(paths: &[PathBuf]) -> Self {
+        let mut known_hosts = KnownHosts::default();
+        for path in paths {
+            if let Ok(content) = fs::read_to_string(path) {
+                known_hosts.parse(&content, path);
+            }
+        }
+        known_hosts
+    }
+
+    fn parse(&mut self, content: &str, path: &Path) {
+        for (index, line) in content.lines().enumerate() {
+            let Some((marker, hosts, key)) = parse_line(line) else {
+                continue;
+            };
+            self.entries.push(Entry {
+                marker,
+                hosts: hosts.to_string(),
+                key,
+                path: path.to_path_buf(),
+                line: index + 1,
+            });
+        }
+    }
+
+    pub fn check(&self, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+        let name = lookup_name(host, port);
+        let mut changed = None;
+        let mut authority = None;
+        let mut known = false;
+
+        for entry in self.entries.iter().filter(|e| match_hosts(&name, &e.hosts)) {
+            let same_key = entry.key.key_data() == key.key_data();
+            match entry.marker {
+                Some(Marker::Revoked) if same_key => {
+                    return HostKeyStatus::Revoked { path: entry.path.clone(), line: entry.line };
+                }
+                Some(Marker::CertAuthority) if authority.is_none() => {
+                    authority = Some((entry.path.clone(), entry.line));
+                }
+                Some(_) => {}
+                None if same_key => known = true,
+                None if entry.key.algorithm() == key.algorithm() && changed.is_none() => {
+                    changed = Some((entry.path.clone(), entry.line));
+                }
+                None => {}
+            }
+        }
+
+        match (known, changed) {
+            (true, _) => HostKeyStatus::Known,
+            (false, Some((path, line))) => HostKeyStatus::Changed { path, line },
+            (false, None) => match authority {
+                Some((path, line)) => HostKeyStatus::CertAuthority { path, line },
+                None => HostKeyStatus::Unknown,
+            },
+        }
+    }
+}
+
+// Comments, blank lines, unknown markers and malformed lines give None.
+fn parse_line(line: &str) -> Option<(Option<Marker>, &str, PublicKey)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = line.split_whitespace();
+    let mut hosts = fields.next();
+    let marker = match hosts {
+        Some("@cert-authority") => Some(Marker::CertAuthority),
+        Some("@revoked") => Some(Marker::Revoked),
+        Some(m) if m.starts_with('@') => return None,
+        _ => None,
+    };
+    if marker.is_some() {
+        hosts = fields.next();
+    }
+
+    let (Some(hosts), Some(_key_type), Some(key_base64)) = (hosts, fields.next(), fields.next()) else {
+        return None;
+    };
+    let key = russh::keys::parse_public_key_base64(key_base64).ok()?;
+    Some((marker, hosts, key))
+}
+
+pub fn lookup_name(host: &str, port: u16) -> String {
+    let host = host.to_ascii_lowercase();
+    if port == 22 {
+        host
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+fn match_hosts(name: &str, hosts: &str) -> bool {
+    if let Some(hashed) = hosts.strip_prefix(HASH_MAGIC) {
+        return match_hashed(name, hashed);
+    }
+
+    let mut matched = false;
+    for pattern in hosts.split(',') {
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if wildcard_match(&negated.to_ascii_lowercase(), name) {
+                return false;
+            }
+        } else if wildcard_match(&pattern.to_ascii_lowercase(), name) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+fn match_hashed(name: &str, hashed: &str) -> bool {
+    let Some((salt, hash)) = hashed.split_once(HASH_DELIM) else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (BASE64.decode(salt.as_bytes()), BASE64.decode(hash.as_bytes())) else {
+        return false;
+    };
+    let Ok(mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.chain_update(name.as_bytes()).verify_slice(&hash).is_ok()
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+pub fn append_hashed(path: &Path, host: &str, port: u16, key: &PublicKey) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.exists() {
+            fs::create_dir_all(dir)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+            }
+        }
+    }
+
+    let salt: [u8; 20] = rand::random();
+    let mac = Hmac::<Sha1>::new_from_slice(&salt)
+        .expect("HMAC accepts any key length")
+        .chain_update(lookup_name(host, port).as_bytes())
+        .finalize()
+        .into_bytes();
+
+    let key = key
+        .to_openssh()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{}{}{}{} {}",
+        HASH_MAGIC,
+        BASE64.encode(&salt),
+        HASH_DELIM,
+        BASE64.encode(&mac),
+        key.trim_end()
+    )
+}
+
+// Comments out the lines that give the host another key of the same type, as
+// a changed key is trusted in their place; returns how many there were.
+pub fn comment_out_changed(path: &Path, host: &str, port: u16, key: &PublicKey) -> std::io::Result<usize> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let name = lookup_name(host, port);
+    let mut replaced = 0;
+    let mut updated = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let stale = matches!(
+            parse_line(line),
+            Some((None, hosts, old)) if match_hosts(&name, hosts)
+                && old.algorithm() == key.algorithm()
+                && old.key_data() != key.key_data()
+        );
+        if stale {
+            updated.push_str(CHANGED_PREFIX);
+            replaced += 1;
+        }
+        updated.push_str(line);
+    }
+
+    if replaced > 0 {
+        fs::write(path, updated)?;
+    }
+    Ok(replaced)
+}
+
+pub fn fingerprint(key: &PublicKey) -> String {
+    key.fingerprint(HashAlg::Sha256).to_string()
+}
+
+pub fn randomart(key: &PublicKey) -> String {
+    let (name, bits) = match key.key_data() {
+        KeyData::Ed25519(_) => ("ED25519", 256),
+        KeyData::SkEd25519(_) => ("ED25519-SK", 256),
+        KeyData::Ecdsa(ecdsa) => ("ECDSA", match ecdsa.curve() {
+            EcdsaCurve::NistP256 => 256,
+            EcdsaCurve::NistP384 => 384,
+            EcdsaCurve::NistP521 => 521,
+        }),
+        KeyData::SkEcdsaSha2NistP256(_) => ("ECDSA-SK", 256),
+        KeyData::Rsa(rsa) => ("RSA", rsa.n.as_positive_bytes().map_or(0, |n| n.len() as u32 * 8)),
+        KeyData::Dsa(_) => ("DSA", 1024),
+        _ => ("UNKNOWN", 0),
+    };
+    key.fingerprint(HashAlg::Sha256)
+        .to_randomart(&format!("[{} {}]", name, bits))
+}
+
+pub fn default_user_files() -> Vec<PathBuf> {
+    match home::home_dir() {
+        Some(home) => vec![
+            home.join(".ssh").join("known_hosts"),
+            home.join(".ssh").join("known_hosts2"),
+        ],
+        None => Vec::new(),
+    }
+}
+
+pub fn default_global_files() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from("/etc/ssh/ssh_known_hosts"),
+        PathBuf::from("/etc/ssh/ssh_known_hosts2"),
+    ]
+}
+
+pub fn expand_path(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+pub struct HostKeyVerifier {
+    pub policy: StrictHostKeyChecking,
+    pub user_files: Vec<PathBuf>,
+    pub global_files: Vec<PathBuf>,
+}
+
+impl HostKeyVerifier {
+    pub fn status(&self, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+        let paths: Vec<PathBuf> = self.user_files.iter().chain(self.global_files.iter()).cloned().collect();
+        KnownHosts::load(&paths).check(host, port, key)
+    }
+
+    pub fn remember(&self, host: &str, port: u16, key: &PublicKey) -> std::io::Result<()> {
+        match self.user_files.first() {
+            Some(path) => append_hashed(path, host, port, key),
+            None => Ok(()),
+        }
+    }
+
+    // As `remember`, for a changed key: the user files' lines with the old key
+    // are commented out first. Global files are left as they are.
+    pub fn replace(&self, host: &str, port: u16, key: &PublicKey) -> std::io::Result<()> {
+        for path in &self.user_files {
+            comment_out_changed(path, host, port, key)?;
+        }
+        self.remember(host, port, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKDExRH+TK8WbfSTMY/DVtTZfCYLZ+XLsCTqqxsP+/xK";
+    const OTHER_ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAUKwrL4FVV3ngY9OU32GKLQFjoQ9tM6ln+c3oDKGjdW";
+    const ECDSA: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBOxXofoYqiGPqda5COAbUeOXlUkgKd6GCM0SkOBqsDsonzkR4IJHv7CxLYt1MivaXtbSAFdcZ3xPIhOHCYNogcg=";
+
+    fn key(line: &str) -> PublicKey {
+        PublicKey::from_openssh(line).unwrap()
+    }
+
+    fn known_hosts(content: &str) -> KnownHosts {
+        let mut known_hosts = KnownHosts::default();
+        known_hosts.parse(content, Path::new("known_hosts"));
+        known_hosts
+    }
+
+    fn check(content: &str, host: &str, port: u16, line: &str) -> HostKeyStatus {
+        known_hosts(content).check(host, port, &key(line))
+    }
+
+    #[test]
+    fn matches_plain_host_names() {
+        let content = format!("example.com,other.org {}\n", ED25519);
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Known);
+        assert_eq!(check(&content, "OTHER.org", 22, ED25519), HostKeyStatus::Known);
+        assert_eq!(check(&content, "example.net", 22, ED25519), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn negation_wins_over_any_positive_pattern() {
+        for hosts in ["*.example.com,!bad.example.com", "!bad.example.com,*.example.com"] {
+            let content = format!("{} {}\n", hosts, ED25519);
+            assert_eq!(check(&content, "good.example.com", 22, ED25519), HostKeyStatus::Known);
+            assert_eq!(check(&content, "bad.example.com", 22, ED25519), HostKeyStatus::Unknown);
+        }
+        // A negation alone matches nothing.
+        let content = format!("!bad.example.com {}\n", ED25519);
+        assert_eq!(check(&content, "good.example.com", 22, ED25519), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn matches_lines_hashed_by_openssh() {
+        // From `ssh-keygen -H` over "example.com" and "[example.com]:2222".
+        let content = format!(
+            "|1|GAqmZAWlJ92VzWB855LmswqbCk8=|oaQketPXjH6JbOvmJEayEx2fUIk= {key}\n\
+             |1|3gQWUpOXdrMhb7sdSZqAc/SwisA=|0iE/cldAfnQlnJdSLFaG2y9bD74= {key}\n",
+            key = ED25519
+        );
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Known);
+        assert_eq!(check(&content, "example.com", 2222, ED25519), HostKeyStatus::Known);
+        assert_eq!(check(&content, "example.com", 2200, ED25519), HostKeyStatus::Unknown);
+        assert_eq!(check(&content, "example.org", 22, ED25519), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert!(!match_hashed("example.com", "no-delimiter"));
+        assert!(!match_hashed("example.com", "!!!|!!!"));
+        assert!(!match_hashed("example.com", "GAqmZAWlJ92VzWB855LmswqbCk8=|AAAA"));
+    }
+
+    #[test]
+    fn wildcards_backtrack() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("h?st", "host"));
+        assert!(!wildcard_match("h?st", "hst"));
+        assert!(wildcard_match("a*b*c", "axbybzc"));
+        assert!(wildcard_match("*.example.com", "a.b.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("*c*", "abcbc"));
+        assert!(!wildcard_match("a*b", "aXbY"));
+        assert!(wildcard_match("a**", "a"));
+    }
+
+    #[test]
+    fn non_default_ports_use_bracketed_names() {
+        assert_eq!(lookup_name("Example.COM", 22), "example.com");
+        assert_eq!(lookup_name("example.com", 2222), "[example.com]:2222");
+
+        let content = format!("[example.com]:2222 {}\n", ED25519);
+        assert_eq!(check(&content, "example.com", 2222, ED25519), HostKeyStatus::Known);
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Unknown);
+
+        let content = format!("[*.example.com]:* {}\n", ED25519);
+        assert_eq!(check(&content, "a.example.com", 2222, ED25519), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn revoked_wins_wherever_it_appears() {
+        let revoked = HostKeyStatus::Revoked { path: PathBuf::from("known_hosts"), line: 2 };
+        let content = format!("example.com {key}\n@revoked * {key}\n", key = ED25519);
+        assert_eq!(check(&content, "example.com", 22, ED25519), revoked);
+
+        let content = format!("@revoked * {key}\nexample.com {key}\n", key = ED25519);
+        let revoked = HostKeyStatus::Revoked { path: PathBuf::from("known_hosts"), line: 1 };
+        assert_eq!(check(&content, "example.com", 22, ED25519), revoked);
+
+        // Revoking another key leaves this one alone.
+        let content = format!("example.com {}\n@revoked * {}\n", ED25519, OTHER_ED25519);
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn changed_only_for_the_same_algorithm() {
+        let content = format!("# comment\n\nexample.com {}\n", OTHER_ED25519);
+        assert_eq!(
+            check(&content, "example.com", 22, ED25519),
+            HostKeyStatus::Changed { path: PathBuf::from("known_hosts"), line: 3 }
+        );
+
+        // A key of another type is a new key, not a changed one.
+        let content = format!("example.com {}\n", ECDSA);
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Unknown);
+
+        // Any matching line makes the key known, whatever else is listed.
+        let content = format!("example.com {}\nexample.com {}\n", OTHER_ED25519, ED25519);
+        assert_eq!(check(&content, "example.com", 22, ED25519), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn cert_authority_is_reported_but_not_trusted() {
+        let content = format!("@cert-authority *.example.com {}\n", ED25519);
+        assert_eq!(
+            check(&content, "a.example.com", 22, ED25519),
+            HostKeyStatus::CertAuthority { path: PathBuf::from("known_hosts"), line: 1 }
+        );
+        assert_eq!(check(&content, "example.org", 22, ED25519), HostKeyStatus::Unknown);
+
+        let content = format!("@cert-authority * {}\na.example.com {}\n", ED25519, ED25519);
+        assert_eq!(check(&content, "a.example.com", 22, ED25519), HostKeyStatus::Known);
+    }
+
+    #[test]
+    fn skips_unknown_markers_and_bad_lines() {
+        let content = format!("@future * {key}\nexample.com\nexample.com ssh-ed25519 !!!\n", key = ED25519);
+        assert!(known_hosts(&content).entries.is_empty());
+    }
+
+    #[test]
+    fn comments_out_only_the_changed_lines() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        let path = std::env::temp_dir().join(format!("known-hosts-changed-{}-{}", std::process::id(), nanos));
+        let content = format!(
+            "example.com {old}\nexample.com {ecdsa}\nexample.org {old}\n@revoked example.com {old}\n",
+            old = OTHER_ED25519,
+            ecdsa = ECDSA
+        );
+        fs::write(&path, &content).unwrap();
+
+        assert_eq!(comment_out_changed(&path, "example.com", 22, &key(ED25519)).unwrap(), 1);
+        let updated = fs::read_to_string(&path).unwrap();
+        assert_eq!(updated, format!("{}{}", CHANGED_PREFIX, content));
+        assert_eq!(KnownHosts::load(std::slice::from_ref(&path)).check("example.com", 22, &key(ED25519)), HostKeyStatus::Unknown);
+
+        // Nothing left to replace, so the file is not rewritten.
+        assert_eq!(comment_out_changed(&path, "example.com", 22, &key(ED25519)).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(comment_out_changed(&path, "example.com", 22, &key(ED25519)).unwrap(), 0);
+    }
+
+    #[test]
+    fn append_hashed_round_trips() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = std::env::temp_dir().join(format!("known-hosts-{}-{}", std::process::id(), nanos));
+        let path = dir.join(".ssh").join("known_hosts");
+
+        append_hashed(&path, "Example.com", 2222, &key(ED25519)).unwrap();
+        append_hashed(&path, "example.org", 22, &key(ECDSA)).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("example"));
+        assert!(content.lines().all(|line| line.starts_with(HASH_MAGIC)));
+
+        let known_hosts = KnownHosts::load(&[path]);
+        assert_eq!(known_hosts.check("example.com", 2222, &key(ED25519)), HostKeyStatus::Known);
+        assert_eq!(known_hosts.check("example.com", 22, &key(ED25519)), HostKeyStatus::Unknown);
+        assert_eq!(known_hosts.check("example.org", 22, &key(ECDSA)), HostKeyStatus::Known);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -3,19 +3,83 @@ use napi_derive::napi;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use russh::*;
-use russh_sftp::client::SftpSession;
-use russh_sftp::protocol::OpenFlags;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod agent;
+mod algorithms;
+mod auth;
+mod callback;
+pub mod cancel;
+mod dial;
+pub mod exec;
+pub mod forward;
+mod glob;
+mod identity;
+mod integrity;
+mod known_hosts;
+mod liveness;
+mod proxy;
+mod reconnect;
+pub mod sftp;
+pub mod shell;
+mod socks;
+pub mod sync;
+pub mod transfer;
+
+use callback::{Callback, Prompt};
+use known_hosts::{HostKeyStatus, HostKeyVerifier, StrictHostKeyChecking};
+
+struct Session {
+    id: u32,
+    host: String,
+    port: u16,
+    username: String,
+    // Authentication methods that completed and the identity that was accepted.
+    auth_methods: Vec<String>,
+    identity: Option<String>,
+    jump_hosts: Vec<String>,
+    started_at: SystemTime,
+    // Commands currently running, for session info.
+    execs: AtomicU32,
+    // Write access is only taken for tcpip-forward and streamlocal-forward,
+    // which russh takes by &mut self; their cancels and everything else read.
+    handle: tokio::sync::RwLock<client::Handle<Client>>,
+    // Jump host sessions carrying `handle`, first hop first.
+    jumps: Mutex<Vec<client::Handle<Client>>>,
+    // ProxyCommand carrying the first hop, killed on disconnect or drop.
+    proxy: Mutex<Option<tokio::process::Child>>,
+    // ProxyCommand stderr and errors from agent forwarding and forwards.
+    log: proxy::Log,
+    remote_forwards: forward::RemoteForwards,
+    forward_agent: bool,
+    sftp: sftp::Cache,
+    liveness: liveness::Liveness,
+    // Algorithms of the latest key exchange with the target.
+    algorithms: algorithms::Negotiated,
+    // How to connect again after the connection is lost; None when the
+    // session should just go away.
+    reconnect: Option<reconnect::Reconnect>,
+}
 
-static SESSIONS: Lazy<Mutex<HashMap<u32, Arc<client::Handle<Client>>>>> =
+static SESSIONS: Lazy<Mutex<HashMap<u32, Arc<Session>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+// Ids are never reused, so a stale id cannot reach a newer session.
+static NEXT_SESSION_ID: AtomicU32 = AtomicU32::new(1);
+
+fn get_session(session_id: u32) -> Result<Arc<Session>> {
+    SESSIONS.lock()
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Invalid session"))
+}
+
 #[napi]
-pub fn load_ssh_key_info(key_path: String) -> Result<String> {
-    match russh::keys::load_secret_key(&key_path, None) {
+pub fn load_ssh_key_info(key_path: String, passphrase: Option<String>) -> Result<String> {
+    match russh::keys::load_secret_key(&key_path, passphrase.as_deref()) {
         Ok(_) => Ok(format!("Successfully loaded key: {}", key_path)),
         Err(e) => Err(napi::Error::new(Status::GenericFailure, format!("Load: {}", e)))
     }
@@ -30,17 +94,296 @@ pub fn test_certificate_detection(cert_path: String) -> Result<bool> {
     }
 }
 
-struct Client;
+#[napi(object)]
+pub struct HostKeyPrompt {
+    pub host: String,
+    pub port: u32,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub randomart: String,
+    pub changed: bool,
+    /// Known-hosts line whose `@cert-authority` covers the host; such lines
+    /// are not supported, so the key is treated as unknown.
+    pub cert_authority: Option<String>,
+}
+
+#[napi(object)]
+pub struct PassphrasePrompt {
+    pub key_path: String,
+    pub attempt: u32,
+}
+
+#[napi(object, object_to_js = false)]
+#[derive(Clone, Default)]
+pub struct ConnectOptions {
+    /// One of "yes", "ask", "accept-new" or "no"; defaults to "ask".
+    pub strict_host_key_checking: Option<String>,
+    pub user_known_hosts_files: Option<Vec<String>>,
+    pub global_known_hosts_files: Option<Vec<String>>,
+    /// Asked to confirm unknown or changed host keys; resolves to `true` to trust the key.
+    #[napi(ts_type = "(err: null, prompt: HostKeyPrompt) => boolean | Promise<boolean>")]
+    pub on_host_key: Option<Prompt<HostKeyPrompt>>,
+    /// Agent socket or pipe, as in IdentityAgent; defaults to SSH_AUTH_SOCK.
+    pub identity_agent: Option<String>,
+    pub forward_agent: Option<bool>,
+    pub passphrase: Option<String>,
+    /// Asked for the passphrase of an encrypted key; resolve to `null` to give up.
+    #[napi(ts_type = "(err: null, prompt: PassphrasePrompt) => string | null | Promise<string | null>")]
+    pub on_passphrase: Option<Prompt<PassphrasePrompt>>,
+    pub password: Option<String>,
+    #[napi(ts_type = "(err: null, prompt: PasswordPrompt) => string | null | Promise<string | null>")]
+    pub on_password: Option<Prompt<auth::PasswordPrompt>>,
+    /// Answers keyboard-interactive challenges; one response per prompt.
+    #[napi(ts_type = "(err: null, prompt: KeyboardInteractivePrompt) => Array<string> | null | Promise<Array<string> | null>")]
+    pub on_keyboard_interactive: Option<Prompt<auth::KeyboardInteractivePrompt>>,
+    /// Method names in PreferredAuthentications order.
+    pub preferred_authentications: Option<Vec<String>>,
+    /// Hosts to tunnel through, first hop first, as in ProxyJump.
+    pub jump_hosts: Option<Vec<JumpHost>>,
+    /// Command whose stdin/stdout carry the connection, with %h, %p, %r and %n expanded.
+    pub proxy_command: Option<String>,
+    /// Host name as given by the user, for %n; defaults to the host.
+    pub host_alias: Option<String>,
+    /// Seconds of silence from the server before a keepalive is sent, as in
+    /// ServerAliveInterval; 0 or unset sends none.
+    pub server_alive_interval: Option<u32>,
+    /// Unanswered keepalives before the connection is dropped, as in
+    /// ServerAliveCountMax; defaults to 3.
+    pub server_alive_count_max: Option<u32>,
+    /// Told when the connection goes away other than through `sshDisconnect`;
+    /// the session is already gone from the registry by then.
+    #[napi(ts_type = "(event: SessionEvent) => void")]
+    pub on_event: Option<Callback<liveness::SessionEvent>>,
+    /// Connect again with the same credentials when the connection is lost,
+    /// keeping the session id and restoring forwards.
+    pub reconnect: Option<reconnect::ReconnectOptions>,
+    /// Limit on resolving and reaching the host, as in ConnectTimeout; unset or
+    /// 0 waits for the operating system.
+    pub connect_timeout_ms: Option<u32>,
+    /// Limit on the SSH handshake once connected, up to the end of key
+    /// exchange: authentication is not included, nor is time spent on host
+    /// key prompts. Unset or 0 waits indefinitely.
+    pub handshake_timeout_ms: Option<u32>,
+    /// Token from `sshCreateCancelToken`; cancelling it abandons the connection attempt.
+    pub cancel_id: Option<u32>,
+    /// One of "inet", "inet6" or "any", as in AddressFamily; defaults to "any".
+    pub address_family: Option<String>,
+    /// Local address to connect from, as in BindAddress.
+    pub bind_address: Option<String>,
+    /// Network interface to connect through, as in BindInterface; Linux only.
+    pub bind_interface: Option<String>,
+    /// Algorithm lists in OpenSSH syntax: a plain list replaces the defaults,
+    /// and a leading "+", "-" or "^" appends to, removes from or goes before them.
+    pub ciphers: Option<String>,
+    pub kex_algorithms: Option<String>,
+    pub macs: Option<String>,
+    pub host_key_algorithms: Option<String>,
+}
+
+#[napi(object, object_to_js = false)]
+#[derive(Clone, Default)]
+pub struct JumpHost {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: String,
+    /// Defaults to every key in the agent.
+    pub identities: Option<Vec<auth::Identity>>,
+    /// Host key and authentication settings for this hop; its `jumpHosts` is ignored.
+    pub options: Option<ConnectOptions>,
+}
+
+struct Client {
+    host: String,
+    port: u16,
+    verifier: HostKeyVerifier,
+    on_host_key: Option<Prompt<HostKeyPrompt>>,
+    forward_agent_to: Option<String>,
+    remote_forwards: forward::RemoteForwards,
+    liveness: liveness::Liveness,
+    negotiated: algorithms::Negotiated,
+    log: proxy::Log,
+    // Host key prompts, which pause the handshake timeout.
+    prompts: Arc<dial::Prompts>,
+}
+
+impl Client {
+    async fn confirm_host_key(&self, key: &keys::PublicKey, changed: bool, cert_authority: Option<String>) -> anyhow::Result<bool> {
+        let Some(on_host_key) = &self.on_host_key else {
+            return Ok(false);
+        };
+
+        let prompt = HostKeyPrompt {
+            host: self.host.clone(),
+            port: self.port as u32,
+            key_type: key.algorithm().to_string(),
+            fingerprint: known_hosts::fingerprint(key),
+            randomart: known_hosts::randomart(key),
+            changed,
+            cert_authority,
+        };
+        let open = self.prompts.open();
+        let answer = callback::ask::<_, bool>(on_host_key, prompt).await;
+        drop(open);
+        let answer = answer?;
+
+        if answer && changed {
+            self.verifier.replace(&self.host, self.port, key)?;
+        } else if answer {
+            self.verifier.remember(&self.host, self.port, key)?;
+        }
+        Ok(answer)
+    }
+
+    async fn unknown_host_key(&self, key: &keys::PublicKey, cert_authority: Option<String>) -> anyhow::Result<bool> {
+        match self.verifier.policy {
+            StrictHostKeyChecking::AcceptNew | StrictHostKeyChecking::No => {
+                self.verifier.remember(&self.host, self.port, key)?;
+                Ok(true)
+            }
+            StrictHostKeyChecking::Ask if self.confirm_host_key(key, false, cert_authority.clone()).await? => Ok(true),
+            StrictHostKeyChecking::Yes | StrictHostKeyChecking::Ask => {
+                let mut message = format!(
+                    "No {} host key is known for {} ({})",
+                    key.algorithm(), self.host, known_hosts::fingerprint(key)
+                );
+                if let Some(authority) = cert_authority {
+                    message.push_str(&format!(
+                        "; the host is covered by @cert-authority ({}), which is not supported",
+                        authority
+                    ));
+                }
+                Err(anyhow::anyhow!(message))
+            }
+        }
+    }
+}
 
 impl client::Handler for Client {
-    type Error = russh::Error;
+    type Error = anyhow::Error;
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &keys::PublicKey,
+        server_public_key: &keys::PublicKey,
     ) -> std::result::Result<bool, Self::Error> {
-        Ok(true)
+        let fingerprint = known_hosts::fingerprint(server_public_key);
+        match self.verifier.status(&self.host, self.port, server_public_key) {
+            HostKeyStatus::Known => Ok(true),
+            HostKeyStatus::Revoked { path, line } => Err(anyhow::anyhow!(
+                "Host key {} for {} is revoked ({}:{})",
+                fingerprint, self.host, path.display(), line
+            )),
+            HostKeyStatus::Changed { path, line } => {
+                // accept-new only trusts keys for hosts not yet known.
+                let may_ask = !matches!(self.verifier.policy, StrictHostKeyChecking::Yes | StrictHostKeyChecking::AcceptNew);
+                if may_ask && self.confirm_host_key(server_public_key, true, None).await? {
+                    return Ok(true);
+                }
+                Err(anyhow::anyhow!(
+                    "Host key for {} has changed to {} (offending key in {}:{})",
+                    self.host, fingerprint, path.display(), line
+                ))
+            }
+            HostKeyStatus::Unknown => self.unknown_host_key(server_public_key, None).await,
+            HostKeyStatus::CertAuthority { path, line } => {
+                let authority = format!("{}:{}", path.display(), line);
+                self.unknown_host_key(server_public_key, Some(authority)).await
+            }
+        }
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: Channel<client::Msg>,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        let Some(agent_path) = self.forward_agent_to.clone() else {
+            let _ = channel.close().await;
+            return Ok(());
+        };
+
+        let log = self.log.clone();
+        tokio::spawn(async move {
+            match agent::open(&agent_path).await {
+                Ok(socket) => {
+                    if let Err(e) = forward::pump(socket, channel, std::future::pending()).await {
+                        proxy::push_log(&log, format!("Agent forward error: {}", e));
+                    }
+                }
+                Err(e) => {
+                    proxy::push_log(&log, format!("Agent forward error: {}", e));
+                    let _ = channel.close().await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        // Servers may echo a normalized bind address, so fall back to the port.
+        let target = {
+            let forwards = &self.remote_forwards.lock().tcpip;
+            forwards
+                .get(&(connected_address.to_string(), connected_port))
+                .or_else(|| forwards.iter().find(|((_, port), _)| *port == connected_port).map(|(_, t)| t))
+                .cloned()
+        };
+        forward::connect_back(channel, target, self.log.clone());
+        Ok(())
     }
+
+    async fn server_channel_open_forwarded_streamlocal(
+        &mut self,
+        channel: Channel<client::Msg>,
+        socket_path: &str,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        let target = self.remote_forwards.lock().streamlocal.get(socket_path).cloned();
+        forward::connect_back(channel, target, self.log.clone());
+        Ok(())
+    }
+
+    async fn kex_done(
+        &mut self,
+        _shared_secret: Option<&[u8]>,
+        names: &Names,
+        _session: &mut client::Session,
+    ) -> std::result::Result<(), Self::Error> {
+        algorithms::record(&self.negotiated, names);
+        Ok(())
+    }
+
+    async fn disconnected(
+        &mut self,
+        reason: client::DisconnectReason<Self::Error>,
+    ) -> std::result::Result<(), Self::Error> {
+        self.liveness.lost(reason).await
+    }
+}
+
+fn host_key_verifier(options: &ConnectOptions) -> Result<HostKeyVerifier> {
+    let policy = match options.strict_host_key_checking.as_deref() {
+        Some(value) => StrictHostKeyChecking::parse(value)
+            .map_err(|e| napi::Error::new(Status::InvalidArg, e))?,
+        None => StrictHostKeyChecking::Ask,
+    };
+    let user_files = match &options.user_known_hosts_files {
+        Some(files) => files.iter().map(|f| known_hosts::expand_path(f)).collect(),
+        None => known_hosts::default_user_files(),
+    };
+    let global_files = match &options.global_known_hosts_files {
+        Some(files) => files.iter().map(|f| known_hosts::expand_path(f)).collect(),
+        None => known_hosts::default_global_files(),
+    };
+
+    Ok(HostKeyVerifier { policy, user_files, global_files })
 }
 
 #[napi]
@@ -50,214 +393,330 @@ pub async fn ssh_connect(
     username: String,
     key_path: String,
     cert_path: Option<String>,
+    options: Option<ConnectOptions>,
 ) -> Result<u32> {
-    let key_pair = russh::keys::load_secret_key(&key_path, None)
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Key load: {}", e)))?;
-
-    let openssh_cert = if let Some(cert_path) = cert_path {
-        Some(russh::keys::load_openssh_certificate(&cert_path)
-            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Cert load: {}", e)))?)
-    } else {
-        None
-    };
-
-    let config = Arc::new(client::Config::default());
-    let sh = Client {};
-
-    let mut session = client::connect(config, (host.as_str(), port), sh)
-        .await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Connect: {}", e)))?;
-
-    let auth_res = if let Some(cert) = openssh_cert {
-        session.authenticate_openssh_cert(username, Arc::new(key_pair), cert).await
-    } else {
-        session.authenticate_publickey(username, keys::PrivateKeyWithHashAlg::new(Arc::new(key_pair), None)).await
-    }.map_err(|e| napi::Error::new(Status::GenericFailure, format!("Auth: {}", e)))?;
-
-    if !auth_res.success() {
-        return Err(napi::Error::new(Status::GenericFailure, "Auth failed"));
+    // Only the given key when it exists; otherwise the agent stands in for it,
+    // so users whose keys live only in the agent can still connect.
+    let key_exists = !key_path.is_empty() && std::path::Path::new(&key_path).exists();
+    let mut identities = vec![auth::Identity { key_path: Some(key_path), cert_path, ..Default::default() }];
+    if !key_exists {
+        identities.push(auth::Identity { agent: Some(true), ..Default::default() });
     }
+    let result = ssh_connect_with_identities(host, port, username, identities, options).await?;
+    Ok(result.session_id)
+}
 
-    let mut sessions = SESSIONS.lock();
-    let session_id = sessions.len() as u32;
-    sessions.insert(session_id, Arc::new(session));
-
-    Ok(session_id)
+#[napi(object)]
+pub struct ConnectResult {
+    pub session_id: u32,
+    pub auth: auth::AuthReport,
 }
 
 #[napi]
-pub async fn ssh_exec(session_id: u32, command: String) -> Result<String> {
-    let session = {
-        let sessions = SESSIONS.lock();
-        sessions.get(&session_id)
-            .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Invalid session"))?
-            .clone()
-    };
-
-    let mut channel = session.channel_open_session().await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Channel: {}", e)))?;
-
-    channel.exec(true, command).await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Exec: {}", e)))?;
+pub async fn ssh_connect_with_identities(
+    host: String,
+    port: u16,
+    username: String,
+    identities: Vec<auth::Identity>,
+    options: Option<ConnectOptions>,
+) -> Result<ConnectResult> {
+    let options = options.unwrap_or_default();
+    let forward_agent = options.forward_agent.unwrap_or(false)
+        && agent::socket_path(options.identity_agent.as_deref()).is_some();
+
+    let log = proxy::Log::default();
+    let jump_hosts = options.jump_hosts.iter().flatten().map(|jump| jump.host.clone()).collect();
+    let remote_forwards = forward::RemoteForwards::default();
+    let liveness = liveness::Liveness::new(options.on_event.clone());
+    let negotiated = algorithms::Negotiated::default();
+    let reconnect = options.reconnect.clone().map(|policy| reconnect::Reconnect {
+        policy,
+        username: username.clone(),
+        identities: identities.clone(),
+        options: options.clone(),
+    });
 
-    let mut output = Vec::new();
-    loop {
-        match channel.wait().await {
-            Some(ChannelMsg::Data { data }) => output.extend_from_slice(&data),
-            Some(ChannelMsg::ExitStatus { .. }) | Some(ChannelMsg::Eof) | None => break,
-            _ => {}
+    let mut token = cancel::subscribe(options.cancel_id);
+    let link = tokio::select! {
+        link = establish(
+            &host,
+            port,
+            username.clone(),
+            identities,
+            &options,
+            forward_agent,
+            &log,
+            remote_forwards.clone(),
+            liveness.clone(),
+            negotiated.clone(),
+        ) => link,
+        _ = cancel::cancelled(token.as_mut()) => {
+            Err(napi::Error::new(Status::Cancelled, "Connect: cancelled"))
         }
+    };
+    cancel::release(options.cancel_id);
+    let link = link?;
+
+    let session_id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    SESSIONS.lock().insert(session_id, Arc::new(Session {
+        id: session_id,
+        host,
+        port,
+        username,
+        auth_methods: link.report.methods.clone(),
+        identity: link.report.identity.clone(),
+        jump_hosts,
+        started_at: SystemTime::now(),
+        execs: AtomicU32::new(0),
+        handle: tokio::sync::RwLock::new(link.handle),
+        jumps: Mutex::new(link.jumps),
+        proxy: Mutex::new(link.proxy),
+        log,
+        remote_forwards,
+        forward_agent,
+        sftp: Default::default(),
+        liveness: liveness.clone(),
+        algorithms: negotiated,
+        reconnect,
+    }));
+
+    // The connection may have dropped before it could be tied to its id.
+    liveness.register(session_id);
+    if get_session(session_id)?.handle.read().await.is_closed() {
+        forget_session(session_id).await;
+        return Err(napi::Error::new(Status::GenericFailure, "Connect: connection closed"));
     }
 
-    Ok(String::from_utf8_lossy(&output).to_string())
+    Ok(ConnectResult { session_id, auth: link.report })
 }
 
-#[napi]
-pub async fn ssh_forward_port(
-    session_id: u32,
-    local_port: u16,
-    remote_host: String,
-    remote_port: u16,
-) -> Result<u16> {
-    let session = {
-        let sessions = SESSIONS.lock();
-        sessions.get(&session_id)
-            .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Invalid session"))?
-            .clone()
-    };
+// A connection to the target, through its jump hosts if any.
+struct Link {
+    handle: client::Handle<Client>,
+    jumps: Vec<client::Handle<Client>>,
+    proxy: Option<tokio::process::Child>,
+    report: auth::AuthReport,
+}
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", local_port))
-        .await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Bind: {}", e)))?;
-
-    let actual_port = listener.local_addr()
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Port: {}", e)))?
-        .port();
-
-    tokio::spawn(async move {
-        loop {
-            if let Ok((stream, addr)) = listener.accept().await {
-                let session = session.clone();
-                let remote_host = remote_host.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = handle_forward(stream, session, addr, remote_host, remote_port).await {
-                        eprintln!("Forward error: {}", e);
-                    }
-                });
+// Connects and authenticates every hop; also used to reconnect a session, in
+// which case `remote_forwards`, `liveness` and `negotiated` are the session's own.
+#[allow(clippy::too_many_arguments)]
+async fn establish(
+    host: &str,
+    port: u16,
+    username: String,
+    identities: Vec<auth::Identity>,
+    options: &ConnectOptions,
+    forward_agent: bool,
+    log: &proxy::Log,
+    remote_forwards: forward::RemoteForwards,
+    liveness: liveness::Liveness,
+    negotiated: algorithms::Negotiated,
+) -> Result<Link> {
+    let mut proxy_child = None;
+    let mut jumps: Vec<client::Handle<Client>> = Vec::new();
+    for jump in options.jump_hosts.clone().unwrap_or_default() {
+        let jump_options = jump.options.unwrap_or_default();
+        let identities = jump.identities
+            .unwrap_or_else(|| vec![auth::Identity { agent: Some(true), ..Default::default() }]);
+        let port = jump.port.unwrap_or(22);
+        let hop = connect_hop(
+            jumps.last(),
+            &jump.host,
+            port,
+            jump.username,
+            identities,
+            &jump_options,
+            false,
+            log,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await;
+        match hop {
+            Ok(hop) => {
+                proxy_child = proxy_child.or(hop.proxy);
+                jumps.push(hop.handle);
+            }
+            Err(e) => {
+                close_jumps(&jumps).await;
+                return Err(napi::Error::new(e.status, format!("Jump host {}: {}", jump.host, e.reason)));
             }
         }
-    });
-
-    Ok(actual_port)
-}
+    }
 
-async fn handle_forward(
-    mut stream: TcpStream,
-    session: Arc<client::Handle<Client>>,
-    originator_addr: std::net::SocketAddr,
-    remote_host: String,
-    remote_port: u16,
-) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut channel = session
-        .channel_open_direct_tcpip(
-            remote_host,
-            remote_port as u32,
-            originator_addr.ip().to_string(),
-            originator_addr.port() as u32,
-        )
-        .await?;
-
-    let mut stream_closed = false;
-    let mut channel_closed = false;
-    let mut buf = vec![0; 65536];
-
-    loop {
-        tokio::select! {
-            r = stream.read(&mut buf), if !stream_closed => {
-                match r {
-                    Ok(0) => {
-                        stream_closed = true;
-                        let _ = channel.eof().await;
-                        if channel_closed {
-                            break;
-                        }
-                    },
-                    Ok(n) => {
-                        if let Err(_) = channel.data(&buf[..n]).await {
-                            break;
-                        }
-                    },
-                    Err(_) => break,
-                }
-            },
-            Some(msg) = channel.wait() => {
-                match msg {
-                    ChannelMsg::Data { ref data } => {
-                        if let Err(_) = stream.write_all(data).await {
-                            break;
-                        }
-                    }
-                    ChannelMsg::Eof => {
-                        channel_closed = true;
-                        let _ = stream.shutdown().await;
-                        if stream_closed {
-                            break;
-                        }
-                    }
-                    ChannelMsg::ExitStatus { .. } => {
-                        channel_closed = true;
-                        if stream_closed {
-                            break;
-                        }
-                    }
-                    ChannelMsg::WindowAdjusted { .. } => {}
-                    _ => {}
-                }
-            },
-            else => break,
+    let hop = connect_hop(
+        jumps.last(),
+        host,
+        port,
+        username,
+        identities,
+        options,
+        forward_agent,
+        log,
+        remote_forwards,
+        liveness,
+        negotiated,
+    )
+    .await;
+    match hop {
+        Ok(hop) => Ok(Link {
+            handle: hop.handle,
+            jumps,
+            proxy: proxy_child.or(hop.proxy),
+            report: hop.report,
+        }),
+        Err(e) => {
+            close_jumps(&jumps).await;
+            Err(e)
         }
     }
+}
 
-    Ok(())
+// False once the session has been disconnected or given up.
+fn is_registered(session: &Arc<Session>) -> bool {
+    SESSIONS.lock().get(&session.id).is_some_and(|registered| Arc::ptr_eq(registered, session))
 }
 
-#[napi]
-pub async fn ssh_upload_file(session_id: u32, local_path: String, remote_path: String) -> Result<()> {
-    let session = {
-        let sessions = SESSIONS.lock();
-        sessions.get(&session_id)
-            .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Invalid session"))?
-            .clone()
+// Unregisters a session whose connection has already gone and releases what
+// it held; false when it was not registered (any more).
+async fn forget_session(session_id: u32) -> bool {
+    let Some(session) = SESSIONS.lock().remove(&session_id) else {
+        return false;
     };
+    forward::close_session(session_id);
+    shell::close_session(session_id);
+    close_transport(&session).await;
+    true
+}
 
-    let local_data = tokio::fs::read(&local_path).await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Read file: {}", e)))?;
-
-    let channel = session.channel_open_session().await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Channel: {}", e)))?;
+async fn close_transport(session: &Session) {
+    let jumps = std::mem::take(&mut *session.jumps.lock());
+    close_jumps(&jumps).await;
+    if let Some(mut child) = session.proxy.lock().take() {
+        let _ = child.start_kill();
+    }
+}
 
-    channel.request_subsystem(true, "sftp").await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("SFTP: {}", e)))?;
+async fn close_jumps(jumps: &[client::Handle<Client>]) {
+    for jump in jumps.iter().rev() {
+        let _ = jump.disconnect(Disconnect::ByApplication, "", "en").await;
+    }
+}
 
-    let sftp = SftpSession::new(channel.into_stream()).await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("SFTP session: {}", e)))?;
+struct Hop {
+    handle: client::Handle<Client>,
+    report: auth::AuthReport,
+    proxy: Option<tokio::process::Child>,
+}
 
-    let mut file = sftp.open_with_flags(
-        &remote_path,
-        OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE,
-    ).await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Open: {}", e)))?;
+// Dials `host` directly, through a direct-tcpip channel on `via` when the
+// connection is the next hop of a jump chain, or over a ProxyCommand.
+#[allow(clippy::too_many_arguments)]
+async fn connect_hop(
+    via: Option<&client::Handle<Client>>,
+    host: &str,
+    port: u16,
+    username: String,
+    identities: Vec<auth::Identity>,
+    options: &ConnectOptions,
+    forward_agent: bool,
+    log: &proxy::Log,
+    remote_forwards: forward::RemoteForwards,
+    liveness: liveness::Liveness,
+    negotiated: algorithms::Negotiated,
+) -> Result<Hop> {
+    let verifier = host_key_verifier(options)?;
+    let agent_path = agent::socket_path(options.identity_agent.as_deref());
+
+    let (keepalive_interval, keepalive_max) =
+        liveness::keepalive(options.server_alive_interval, options.server_alive_count_max);
+    let config = Arc::new(client::Config {
+        preferred: algorithms::preferred(options)?,
+        keepalive_interval,
+        keepalive_max,
+        ..Default::default()
+    });
+    let prompts = Arc::new(dial::Prompts::default());
+    let handshake_timeout = dial::timeout(options.handshake_timeout_ms);
+    let sh = Client {
+        host: host.to_string(),
+        port,
+        verifier,
+        on_host_key: options.on_host_key.clone(),
+        forward_agent_to: if forward_agent { agent_path.clone() } else { None },
+        remote_forwards,
+        liveness,
+        negotiated,
+        log: log.clone(),
+        prompts: prompts.clone(),
+    };
 
-    file.write_all(&local_data).await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Write: {}", e)))?;
+    let mut child = None;
+    let stream: Box<dyn dial::Stream> = match (via, &options.proxy_command) {
+        (Some(jump), _) => {
+            let open = jump.channel_open_direct_tcpip(host, port as u32, "127.0.0.1", 0);
+            let channel = match dial::timeout(options.connect_timeout_ms) {
+                Some(limit) => tokio::time::timeout(limit, open).await
+                    .map_err(|_| napi::Error::new(
+                        Status::GenericFailure,
+                        format!("Channel: timed out after {} ms", limit.as_millis()),
+                    ))?,
+                None => open.await,
+            };
+            let channel = channel
+                .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Channel: {}", e)))?;
+            Box::new(channel.into_stream())
+        }
+        (None, Some(command)) => {
+            let alias = options.host_alias.as_deref().unwrap_or(host);
+            let command = proxy::substitute(command, host, port, &username, alias);
+            let (stream, process) = proxy::spawn(&command, log.clone())
+                .map_err(|e| napi::Error::new(Status::GenericFailure, format!("ProxyCommand: {}", e)))?;
+            child = Some(process);
+            Box::new(stream)
+        }
+        (None, None) => {
+            let stream = dial::Dialer::new(options)?.connect(host, port).await
+                .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Connect: {}", e)))?;
+            Box::new(stream)
+        }
+    };
 
-    file.flush().await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Flush: {}", e)))?;
+    let connected = dial::handshake(client::connect_stream(config, stream, sh), handshake_timeout, &prompts)
+        .await
+        .ok_or_else(|| napi::Error::new(
+            Status::GenericFailure,
+            format!("Connect: handshake timed out after {} ms", handshake_timeout.unwrap_or_default().as_millis()),
+        ))?;
+
+    let mut session = connected.map_err(|e| {
+        let stderr = log.lock().back().cloned();
+        let message = match stderr {
+            Some(line) => format!("Connect: {} ({})", e, line),
+            None => format!("Connect: {}", e),
+        };
+        napi::Error::new(Status::GenericFailure, message)
+    })?;
+
+    let credentials = auth::Credentials {
+        username,
+        host: host.to_string(),
+        identities,
+        agent_path,
+        passphrase: options.passphrase.clone(),
+        on_passphrase: options.on_passphrase.clone(),
+        password: options.password.clone(),
+        on_password: options.on_password.clone(),
+        on_keyboard_interactive: options.on_keyboard_interactive.clone(),
+        preferred: auth::preferred_methods(options.preferred_authentications.as_deref()),
+    };
 
-    file.shutdown().await
-        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Close: {}", e)))?;
+    let report = credentials.authenticate(&mut session).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Auth failed: {}", e)))?;
 
-    Ok(())
+    Ok(Hop { handle: session, report, proxy: child })
 }
 
 #[napi]
@@ -268,9 +727,81 @@ pub async fn ssh_disconnect(session_id: u32) -> Result<()> {
     };
 
     if let Some(session) = session {
-        session.disconnect(Disconnect::ByApplication, "", "en").await
-            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Disconnect: {}", e)))?;
+        forward::close_session(session_id);
+        shell::close_session(session_id);
+        let result = session.handle.read().await.disconnect(Disconnect::ByApplication, "", "en").await;
+        close_transport(&session).await;
+        result.map_err(|e| napi::Error::new(Status::GenericFailure, format!("Disconnect: {}", e)))?;
     }
 
     Ok(())
 }
+
+#[napi]
+pub fn ssh_session_log(session_id: u32) -> Result<Vec<String>> {
+    let session = get_session(session_id)?;
+    let log = session.log.lock();
+    Ok(log.iter().cloned().collect())
+}
+
+#[napi(object)]
+pub struct SessionInfo {
+    pub session_id: u32,
+    pub host: String,
+    pub port: u32,
+    pub username: String,
+    /// Authentication methods that completed, in order.
+    pub auth_methods: Vec<String>,
+    pub identity: Option<String>,
+    /// Jump hosts the connection goes through, first hop first.
+    pub jump_hosts: Vec<String>,
+    /// Milliseconds since the epoch, as from `Date.now()`.
+    pub started_at: i64,
+    pub forwards: Vec<forward::ForwardInfo>,
+    pub shells: u32,
+    pub execs: u32,
+    pub sftp: bool,
+    /// Session channels in use: commands, shells, SFTP and forwarded connections.
+    pub channels: u32,
+    /// Algorithms agreed in the latest key exchange with the target host.
+    pub algorithms: Option<algorithms::NegotiatedAlgorithms>,
+}
+
+impl Session {
+    fn info(&self) -> SessionInfo {
+        let forwards = forward::ssh_list_forwards(Some(self.id));
+        let shells = shell::count(self.id);
+        let execs = self.execs.load(Ordering::Relaxed);
+        let sftp = sftp::is_open(self);
+        let connections: u32 = forwards.iter().map(|f| f.active_connections).sum();
+        SessionInfo {
+            session_id: self.id,
+            host: self.host.clone(),
+            port: self.port as u32,
+            username: self.username.clone(),
+            auth_methods: self.auth_methods.clone(),
+            identity: self.identity.clone(),
+            jump_hosts: self.jump_hosts.clone(),
+            started_at: self.started_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64),
+            channels: shells + execs + sftp as u32 + connections,
+            forwards,
+            shells,
+            execs,
+            sftp,
+            algorithms: self.algorithms.lock().clone(),
+        }
+    }
+}
+
+#[napi]
+pub fn ssh_list_sessions() -> Vec<SessionInfo> {
+    let sessions: Vec<Arc<Session>> = SESSIONS.lock().values().cloned().collect();
+    let mut infos: Vec<SessionInfo> = sessions.iter().map(|session| session.info()).collect();
+    infos.sort_by_key(|info| info.session_id);
+    infos
+}
+
+#[napi]
+pub fn ssh_session_info(session_id: u32) -> Result<SessionInfo> {
+    Ok(get_session(session_id)?.info())
+}
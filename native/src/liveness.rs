@@ -0,0 +1,110 @@
+use crate::callback::Callback;
+use crate::reconnect;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use russh::client::DisconnectReason;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+// As ServerAliveCountMax.
+const DEFAULT_COUNT_MAX: u32 = 3;
+
+#[napi(object)]
+pub struct SessionEvent {
+    pub session_id: u32,
+    /// "disconnected" when the connection failed, "keepalive-timeout" when
+    /// the server stopped answering keepalives, or "server-disconnect".
+    /// Sessions that reconnect also report "reconnecting", "reconnected" and
+    /// "reconnect-failed".
+    pub kind: String,
+    /// SSH_MSG_DISCONNECT reason code, for "server-disconnect".
+    pub reason_code: Option<u32>,
+    pub description: Option<String>,
+    /// Reconnect attempt, counting from 1.
+    pub attempt: Option<u32>,
+    /// Whether the session is gone; false while it is being reconnected.
+    pub closed: bool,
+}
+
+// Links a connection's handler to its registry entry, so that a connection
+// that dies on its own is reported and cleaned up. The id is only known once
+// the session is registered; until then nothing is reported.
+#[derive(Clone, Default)]
+pub(crate) struct Liveness {
+    session_id: Arc<OnceLock<u32>>,
+    on_event: Option<Callback<SessionEvent>>,
+}
+
+impl Liveness {
+    pub fn new(on_event: Option<Callback<SessionEvent>>) -> Self {
+        Liveness { session_id: Default::default(), on_event }
+    }
+
+    // A handler link for another connection attempt, reporting to the same
+    // callback but tied to no session until `register` is called.
+    pub fn unbound(&self) -> Self {
+        Liveness::new(self.on_event.clone())
+    }
+
+    pub fn register(&self, session_id: u32) {
+        let _ = self.session_id.set(session_id);
+    }
+
+    // Called from the handler once the transport has gone; hands back the
+    // error, as russh expects. Sessions closed through `sshDisconnect` are
+    // already unregistered and stay quiet; those with a reconnect policy are
+    // kept and connected again.
+    pub async fn lost(&self, reason: DisconnectReason<anyhow::Error>) -> anyhow::Result<()> {
+        let (kind, reason_code, description, result) = match reason {
+            DisconnectReason::ReceivedDisconnect(info) => {
+                ("server-disconnect", Some(info.reason_code as u32), info.message, Ok(()))
+            }
+            DisconnectReason::Error(e) => {
+                let kind = match e.downcast_ref::<russh::Error>() {
+                    Some(russh::Error::KeepaliveTimeout) => "keepalive-timeout",
+                    _ => "disconnected",
+                };
+                (kind, None, e.to_string(), Err(e))
+            }
+        };
+
+        let Some(&session_id) = self.session_id.get() else { return result };
+        let description = Some(description).filter(|d| !d.is_empty());
+        match crate::get_session(session_id) {
+            Ok(session) if session.reconnect.is_some() => {
+                self.emit(SessionEvent { reason_code, description, ..self.event(kind) });
+                tokio::spawn(reconnect::run(session));
+            }
+            _ => {
+                if crate::forget_session(session_id).await {
+                    self.emit(SessionEvent { reason_code, description, closed: true, ..self.event(kind) });
+                }
+            }
+        }
+        result
+    }
+
+    pub fn event(&self, kind: &str) -> SessionEvent {
+        SessionEvent {
+            session_id: self.session_id.get().copied().unwrap_or_default(),
+            kind: kind.to_string(),
+            reason_code: None,
+            description: None,
+            attempt: None,
+            closed: false,
+        }
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    }
+}
+
+// Keepalive settings in the manner of ServerAliveInterval and
+// ServerAliveCountMax; an interval of 0 turns them off.
+pub(crate) fn keepalive(interval: Option<u32>, count_max: Option<u32>) -> (Option<Duration>, usize) {
+    let interval = interval.filter(|&secs| secs > 0).map(|secs| Duration::from_secs(secs as u64));
+    (interval, count_max.unwrap_or(DEFAULT_COUNT_MAX) as usize)
+}
@@ -0,0 +1,121 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader, Join};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+const LOG_LINES: usize = 1000;
+
+pub type Log = Arc<Mutex<VecDeque<String>>>;
+
+pub type Stream = Join<ChildStdout, ChildStdin>;
+
+pub fn push_log(log: &Log, line: String) {
+    let mut log = log.lock();
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(line);
+}
+
+// Expands the ProxyCommand tokens ssh_config(5) allows: %h, %p, %r, %n and %%.
+pub fn substitute(command: &str, host: &str, port: u16, user: &str, alias: &str) -> String {
+    let mut expanded = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(host),
+            Some('p') => expanded.push_str(&port.to_string()),
+            Some('r') => expanded.push_str(user),
+            Some('n') => expanded.push_str(alias),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let shell = std::env::var("SHELL").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| "/bin/sh".to_string());
+    let mut cmd = Command::new(shell);
+    cmd.arg("-c").arg(format!("exec {}", command));
+    cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").raw_arg(command);
+    cmd
+}
+
+// The child is killed when dropped, so it lives exactly as long as the session
+// that owns it. Its stderr is collected line by line into `log`.
+pub fn spawn(command: &str, log: Log) -> std::io::Result<(Stream, Child)> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(std::io::Error::other("ProxyCommand has no stdio"));
+    };
+
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                push_log(&log, line);
+            }
+        });
+    }
+
+    Ok((tokio::io::join(stdout, stdin), child))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_tokens() {
+        let cases = [
+            ("nc %h %p", "nc example.com 2222"),
+            ("ssh -W %h:%p %r@bastion", "ssh -W example.com:2222 alice@bastion"),
+            ("connect %n", "connect web"),
+            ("echo 100%%", "echo 100%"),
+            ("%%h", "%h"),
+            ("%h%p", "example.com2222"),
+            ("trailing %", "trailing %"),
+            ("unknown %x %d", "unknown %x %d"),
+            ("é%hé", "éexample.comé"),
+            ("", ""),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(substitute(command, "example.com", 2222, "alice", "web"), expected, "{}", command);
+        }
+    }
+
+    #[test]
+    fn keeps_log_bounded() {
+        let log = Log::default();
+        for i in 0..LOG_LINES + 5 {
+            push_log(&log, i.to_string());
+        }
+        let log = log.lock();
+        assert_eq!(log.len(), LOG_LINES);
+        assert_eq!(log.front().map(String::as_str), Some("5"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::auth::Identity;
+use crate::{forward, ConnectOptions, Session};
+use napi_derive::napi;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_ATTEMPTS: u32 = 10;
+const DEFAULT_DELAY_MS: u32 = 1000;
+const DEFAULT_MAX_DELAY_MS: u32 = 30_000;
+
+#[napi(object)]
+#[derive(Clone, Default)]
+pub struct ReconnectOptions {
+    /// Attempts before the session is given up; defaults to 10.
+    pub attempts: Option<u32>,
+    /// Wait before the first attempt, doubled after each failure; defaults to 1000.
+    pub delay_ms: Option<u32>,
+    /// Upper bound for the wait; defaults to 30000.
+    pub max_delay_ms: Option<u32>,
+}
+
+// Everything needed to authenticate again as the session first did.
+pub(crate) struct Reconnect {
+    pub policy: ReconnectOptions,
+    pub username: String,
+    pub identities: Vec<Identity>,
+    pub options: ConnectOptions,
+}
+
+// Reconnects a session whose connection was lost, with exponential backoff.
+// Local and dynamic forwards open their channels through the session and
+// carry on by themselves; remote forwards are requested from the new server
+// connection. Gives the session up when every attempt fails or when it is
+// disconnected in the meantime.
+pub(crate) async fn run(session: Arc<Session>) {
+    let Some(reconnect) = &session.reconnect else { return };
+    let attempts = reconnect.policy.attempts.unwrap_or(DEFAULT_ATTEMPTS).max(1);
+    let max_delay = reconnect.policy.max_delay_ms.unwrap_or(DEFAULT_MAX_DELAY_MS) as u64;
+    let mut delay = (reconnect.policy.delay_ms.unwrap_or(DEFAULT_DELAY_MS) as u64).min(max_delay);
+    let liveness = &session.liveness;
+    let mut last_error = String::new();
+
+    for attempt in 1..=attempts {
+        liveness.emit(crate::liveness::SessionEvent { attempt: Some(attempt), ..liveness.event("reconnecting") });
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        delay = (delay * 2).min(max_delay);
+        if !crate::is_registered(&session) {
+            return;
+        }
+
+        // Each attempt gets its own link, bound to the session only once its
+        // connection is in place: an attempt that fails after key exchange
+        // must not be reported as the session's connection dropping.
+        let attempt_liveness = liveness.unbound();
+        let link = crate::establish(
+            &session.host,
+            session.port,
+            reconnect.username.clone(),
+            reconnect.identities.clone(),
+            &reconnect.options,
+            session.forward_agent,
+            &session.log,
+            session.remote_forwards.clone(),
+            attempt_liveness.clone(),
+            session.algorithms.clone(),
+        )
+        .await;
+
+        let link = match link {
+            Ok(link) => link,
+            Err(e) => {
+                last_error = e.reason;
+                continue;
+            }
+        };
+        if !crate::is_registered(&session) {
+            let _ = link.handle.disconnect(russh::Disconnect::ByApplication, "", "en").await;
+            crate::close_jumps(&link.jumps).await;
+            return;
+        }
+
+        *session.handle.write().await = link.handle;
+        let old_jumps = std::mem::replace(&mut *session.jumps.lock(), link.jumps);
+        crate::close_jumps(&old_jumps).await;
+        let old_proxy = std::mem::replace(&mut *session.proxy.lock(), link.proxy);
+        if let Some(mut child) = old_proxy {
+            let _ = child.start_kill();
+        }
+        // The SFTP channel went with the old connection.
+        *session.sftp.lock().await = None;
+
+        // A connection that dropped before it was bound went unreported.
+        attempt_liveness.register(session.id);
+        if session.handle.read().await.is_closed() {
+            last_error = "connection closed".to_string();
+            continue;
+        }
+
+        let failed = forward::restore_remote(&session).await;
+        liveness.emit(crate::liveness::SessionEvent {
+            attempt: Some(attempt),
+            description: Some(failed.join("; ")).filter(|failed| !failed.is_empty()),
+            ..liveness.event("reconnected")
+        });
+        return;
+    }
+
+    if crate::forget_session(session.id).await {
+        liveness.emit(crate::liveness::SessionEvent {
+            attempt: Some(attempts),
+            description: Some(last_error).filter(|e| !e.is_empty()),
+            closed: true,
+            ..liveness.event("reconnect-failed")
+        });
+    }
+}
@@ -0,0 +1,398 @@
+use crate::{get_session, Session};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use russh_sftp::client::error::Error;
+use russh_sftp::client::RawSftpSession;
+use russh_sftp::protocol::{File, FileAttributes, OpenFlags, StatusCode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub(crate) const POSIX_RENAME: &str = "posix-rename@openssh.com";
+// Every server handles this much per request; larger sizes need limits@openssh.com.
+const DEFAULT_CHUNK: u32 = 32 * 1024;
+
+const S_IFMT: u32 = 0o170000;
+pub(crate) const S_IFDIR: u32 = 0o040000;
+pub(crate) const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+// One sftp subsystem channel per session, opened on first use and shared by
+// every call; requests from concurrent calls are pipelined on it.
+pub(crate) struct Sftp {
+    pub raw: RawSftpSession,
+    extensions: HashMap<String, String>,
+    pub read_len: u32,
+    pub write_len: u32,
+    closed: AtomicBool,
+}
+
+// Held across opening, so that concurrent first calls share one channel.
+pub(crate) type Cache = tokio::sync::Mutex<Option<Arc<Sftp>>>;
+
+impl Sftp {
+    async fn open(session: &Session) -> Result<Sftp> {
+        let channel = session.handle.read().await.channel_open_session().await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Channel: {}", e)))?;
+
+        channel.request_subsystem(true, "sftp").await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("SFTP: {}", e)))?;
+
+        let mut raw = RawSftpSession::new(channel.into_stream());
+        let version = raw.init().await
+            .map_err(|e| napi::Error::new(Status::GenericFailure, format!("SFTP session: {}", e)))?;
+
+        let mut read_len = DEFAULT_CHUNK;
+        let mut write_len = DEFAULT_CHUNK;
+        if version.extensions.contains_key(russh_sftp::extensions::LIMITS) {
+            if let Ok(limits) = raw.limits().await {
+                // Zero means the server sets no limit of its own.
+                if limits.max_read_len > 0 {
+                    read_len = limits.max_read_len.min(u32::MAX as u64) as u32;
+                }
+                if limits.max_write_len > 0 {
+                    write_len = limits.max_write_len.min(u32::MAX as u64) as u32;
+                }
+                raw.set_limits(Arc::new(limits.into()));
+            }
+        }
+
+        Ok(Sftp {
+            raw,
+            extensions: version.extensions,
+            read_len,
+            write_len,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.contains_key(extension)
+    }
+
+    // Builds the napi error for a failed request. Transport-level failures mean
+    // the channel is gone, so the next `get` opens a fresh one.
+    pub fn fail(&self, context: &str, e: Error) -> napi::Error {
+        if matches!(e, Error::UnexpectedBehavior(_) | Error::IO(_)) {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+        napi::Error::new(Status::GenericFailure, format!("{}: {}", context, e))
+    }
+
+    pub async fn stat(&self, path: &str) -> Result<Option<FileAttributes>> {
+        match self.raw.stat(path).await {
+            Ok(attrs) => Ok(Some(attrs.attrs)),
+            Err(e) if is_status(&e, StatusCode::NoSuchFile) => Ok(None),
+            Err(e) => Err(self.fail("Stat", e)),
+        }
+    }
+
+    pub async fn mkdir_all(&self, path: &str, mode: Option<u32>) -> Result<()> {
+        // Walk up to the first directory that exists, then create downwards.
+        let mut missing = Vec::new();
+        let mut current = path.trim_end_matches('/');
+        while !current.is_empty() {
+            match self.stat(current).await? {
+                Some(attrs) if file_type(&attrs) == S_IFDIR => break,
+                Some(_) => {
+                    return Err(napi::Error::new(Status::GenericFailure, format!("Mkdir: {} is not a directory", current)));
+                }
+                None => missing.push(current),
+            }
+            current = match current.rsplit_once('/') {
+                Some(("", _)) | None => "",
+                Some((parent, _)) => parent,
+            };
+        }
+
+        for dir in missing.into_iter().rev() {
+            if let Err(e) = self.raw.mkdir(dir, attributes(mode)).await {
+                // Another client may have created it in the meantime.
+                if !matches!(self.stat(dir).await?, Some(attrs) if file_type(&attrs) == S_IFDIR) {
+                    return Err(self.fail("Mkdir", e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces `new_path` atomically when the server supports posix-rename;
+    // plain SFTP rename refuses to overwrite an existing file.
+    pub async fn rename(&self, old_path: &str, new_path: &str) -> Result<()> {
+        if self.supports(POSIX_RENAME) {
+            self.extended(POSIX_RENAME, &[old_path, new_path]).await
+                .map_err(|e| self.fail("Rename", e))
+        } else {
+            self.raw.rename(old_path, new_path).await
+                .map(|_| ())
+                .map_err(|e| self.fail("Rename", e))
+        }
+    }
+
+    // Sends an extended request whose payload is a list of SSH strings and
+    // which is answered with a plain status.
+    pub async fn extended(&self, request: &str, args: &[&str]) -> std::result::Result<(), Error> {
+        match self.raw.extended(request, ssh_strings(args)).await? {
+            russh_sftp::protocol::Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+            russh_sftp::protocol::Packet::Status(status) => Err(Error::Status(status)),
+            _ => Err(Error::UnexpectedPacket),
+        }
+    }
+
+    // Lists a directory without its "." and ".." entries.
+    pub async fn readdir(&self, path: &str) -> Result<Vec<File>> {
+        let handle = self.raw.opendir(path).await
+            .map_err(|e| self.fail("Opendir", e))?
+            .handle;
+
+        let mut entries = Vec::new();
+        let result = loop {
+            match self.raw.readdir(handle.as_str()).await {
+                Ok(name) => entries.extend(
+                    name.files
+                        .into_iter()
+                        .filter(|file| file.filename != "." && file.filename != ".."),
+                ),
+                Err(e) if is_status(&e, StatusCode::Eof) => break Ok(()),
+                Err(e) => break Err(self.fail("Readdir", e)),
+            }
+        };
+
+        let _ = self.raw.close(handle).await;
+        result.map(|_| entries)
+    }
+
+    pub async fn read(&self, path: &str, offset: u64, length: u32) -> Result<Vec<u8>> {
+        let handle = self.raw.open(path, OpenFlags::READ, FileAttributes::empty()).await
+            .map_err(|e| self.fail("Open", e))?
+            .handle;
+
+        let mut data = Vec::with_capacity(length.min(self.read_len) as usize);
+        let result = loop {
+            let remaining = length.saturating_sub(data.len() as u32);
+            if remaining == 0 {
+                break Ok(());
+            }
+            match self.raw.read(handle.as_str(), offset + data.len() as u64, remaining.min(self.read_len)).await {
+                // An empty reply would otherwise be asked again forever.
+                Ok(chunk) if chunk.data.is_empty() => break Ok(()),
+                // Anything past what was asked for is dropped.
+                Ok(chunk) => data.extend_from_slice(&chunk.data[..chunk.data.len().min(remaining as usize)]),
+                Err(e) if is_status(&e, StatusCode::Eof) => break Ok(()),
+                Err(e) => break Err(self.fail("Read", e)),
+            }
+        };
+
+        let _ = self.raw.close(handle).await;
+        result.map(|_| data)
+    }
+}
+
+pub(crate) async fn get(session: &Session) -> Result<Arc<Sftp>> {
+    let mut cache = session.sftp.lock().await;
+    if let Some(sftp) = cache.as_ref().filter(|sftp| !sftp.closed.load(Ordering::Relaxed)) {
+        return Ok(sftp.clone());
+    }
+
+    let sftp = Arc::new(Sftp::open(session).await?);
+    *cache = Some(sftp.clone());
+    Ok(sftp)
+}
+
+// False while the channel is still being opened.
+pub(crate) fn is_open(session: &Session) -> bool {
+    session.sftp.try_lock().is_ok_and(|cache| {
+        cache.as_ref().is_some_and(|sftp| !sftp.closed.load(Ordering::Relaxed))
+    })
+}
+
+async fn get_sftp(session_id: u32) -> Result<Arc<Sftp>> {
+    let session = get_session(session_id)?;
+    get(&session).await
+}
+
+pub(crate) fn is_status(e: &Error, code: StatusCode) -> bool {
+    matches!(e, Error::Status(status) if status.status_code == code)
+}
+
+pub(crate) fn file_type(attrs: &FileAttributes) -> u32 {
+    attrs.permissions.unwrap_or_default() & S_IFMT
+}
+
+pub(crate) fn attributes(mode: Option<u32>) -> FileAttributes {
+    FileAttributes { permissions: mode, ..FileAttributes::empty() }
+}
+
+fn ssh_strings(values: &[&str]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for value in values {
+        data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        data.extend_from_slice(value.as_bytes());
+    }
+    data
+}
+
+#[napi(object)]
+pub struct SftpStat {
+    pub size: Option<i64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Full `st_mode`, file type bits included.
+    pub mode: Option<u32>,
+    /// Seconds since the epoch.
+    pub atime: Option<u32>,
+    pub mtime: Option<u32>,
+    pub is_file: bool,
+    pub is_directory: bool,
+    pub is_symlink: bool,
+}
+
+impl From<&FileAttributes> for SftpStat {
+    fn from(attrs: &FileAttributes) -> Self {
+        let kind = file_type(attrs);
+        SftpStat {
+            size: attrs.size.map(|size| size as i64),
+            uid: attrs.uid,
+            gid: attrs.gid,
+            mode: attrs.permissions,
+            atime: attrs.atime,
+            mtime: attrs.mtime,
+            is_file: kind == S_IFREG,
+            is_directory: kind == S_IFDIR,
+            is_symlink: kind == S_IFLNK,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct SftpEntry {
+    pub name: String,
+    /// The `ls -l` style line the server sent, if any.
+    pub long_name: String,
+    pub stat: SftpStat,
+}
+
+// Follows symlinks; resolves to null when the path does not exist.
+#[napi]
+pub async fn ssh_sftp_stat(session_id: u32, path: String) -> Result<Option<SftpStat>> {
+    let sftp = get_sftp(session_id).await?;
+    Ok(sftp.stat(&path).await?.as_ref().map(SftpStat::from))
+}
+
+#[napi]
+pub async fn ssh_sftp_lstat(session_id: u32, path: String) -> Result<Option<SftpStat>> {
+    let sftp = get_sftp(session_id).await?;
+    match sftp.raw.lstat(path).await {
+        Ok(attrs) => Ok(Some(SftpStat::from(&attrs.attrs))),
+        Err(e) if is_status(&e, StatusCode::NoSuchFile) => Ok(None),
+        Err(e) => Err(sftp.fail("Lstat", e)),
+    }
+}
+
+#[napi]
+pub async fn ssh_sftp_readdir(session_id: u32, path: String) -> Result<Vec<SftpEntry>> {
+    let sftp = get_sftp(session_id).await?;
+    Ok(sftp
+        .readdir(&path)
+        .await?
+        .into_iter()
+        .map(|file| SftpEntry {
+            stat: SftpStat::from(&file.attrs),
+            name: file.filename,
+            long_name: file.longname,
+        })
+        .collect())
+}
+
+#[napi]
+pub async fn ssh_sftp_mkdir(session_id: u32, path: String, recursive: Option<bool>, mode: Option<u32>) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    if recursive.unwrap_or(false) {
+        return sftp.mkdir_all(&path, mode).await;
+    }
+    sftp.raw.mkdir(path, attributes(mode)).await
+        .map_err(|e| sftp.fail("Mkdir", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_rmdir(session_id: u32, path: String) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    sftp.raw.rmdir(path).await
+        .map_err(|e| sftp.fail("Rmdir", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_remove(session_id: u32, path: String) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    sftp.raw.remove(path).await
+        .map_err(|e| sftp.fail("Remove", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_rename(session_id: u32, old_path: String, new_path: String) -> Result<()> {
+    get_sftp(session_id).await?.rename(&old_path, &new_path).await
+}
+
+// OpenSSH's sftp-server reads SSH_FXP_SYMLINK's arguments in the opposite order
+// to the draft, so the target goes first, as `ln -s` would take it.
+#[napi]
+pub async fn ssh_sftp_symlink(session_id: u32, target: String, link_path: String) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    sftp.raw.symlink(target, link_path).await
+        .map_err(|e| sftp.fail("Symlink", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_readlink(session_id: u32, path: String) -> Result<String> {
+    let sftp = get_sftp(session_id).await?;
+    let name = sftp.raw.readlink(path).await
+        .map_err(|e| sftp.fail("Readlink", e))?;
+    name.files.into_iter().next().map(|file| file.filename)
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Readlink: empty reply"))
+}
+
+#[napi]
+pub async fn ssh_sftp_realpath(session_id: u32, path: String) -> Result<String> {
+    let sftp = get_sftp(session_id).await?;
+    let name = sftp.raw.realpath(path).await
+        .map_err(|e| sftp.fail("Realpath", e))?;
+    name.files.into_iter().next().map(|file| file.filename)
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, "Realpath: empty reply"))
+}
+
+#[napi]
+pub async fn ssh_sftp_chmod(session_id: u32, path: String, mode: u32) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    sftp.raw.setstat(path, attributes(Some(mode))).await
+        .map_err(|e| sftp.fail("Chmod", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_chown(session_id: u32, path: String, uid: u32, gid: u32) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    let attrs = FileAttributes { uid: Some(uid), gid: Some(gid), ..FileAttributes::empty() };
+    sftp.raw.setstat(path, attrs).await
+        .map_err(|e| sftp.fail("Chown", e))?;
+    Ok(())
+}
+
+#[napi]
+pub async fn ssh_sftp_utimes(session_id: u32, path: String, atime: u32, mtime: u32) -> Result<()> {
+    let sftp = get_sftp(session_id).await?;
+    let attrs = FileAttributes { atime: Some(atime), mtime: Some(mtime), ..FileAttributes::empty() };
+    sftp.raw.setstat(path, attrs).await
+        .map_err(|e| sftp.fail("Utimes", e))?;
+    Ok(())
+}
+
+// Reads up to `length` bytes from `offset`; the result is shorter only at end of file.
+#[napi]
+pub async fn ssh_sftp_read(session_id: u32, path: String, offset: Option<i64>, length: u32) -> Result<Buffer> {
+    let sftp = get_sftp(session_id).await?;
+    Ok(sftp.read(&path, offset.unwrap_or(0).max(0) as u64, length).await?.into())
+}
@@ -0,0 +1,139 @@
+use crate::callback::Callback;
+use crate::exec::{self, ExecExit, Output, DEFAULT_COLS, DEFAULT_ROWS, DEFAULT_TERM};
+use crate::get_session;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use russh::{client, ChannelWriteHalf, Pty};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+static SHELLS: Lazy<Mutex<HashMap<u32, Arc<Shell>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_SHELL_ID: AtomicU32 = AtomicU32::new(1);
+
+struct Shell {
+    session_id: u32,
+    channel: ChannelWriteHalf<client::Msg>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ShellOptions {
+    /// Defaults to "xterm-256color".
+    pub term: Option<String>,
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    /// Terminal modes by RFC 4254 name, e.g. `{ ECHO: 1, TTY_OP_ISPEED: 38400 }`.
+    pub modes: Option<HashMap<String, u32>>,
+}
+
+fn terminal_mode(name: &str) -> Result<Pty> {
+    (1..=u8::MAX)
+        .filter_map(Pty::from_u8)
+        .find(|mode| format!("{:?}", mode).eq_ignore_ascii_case(name))
+        .ok_or_else(|| napi::Error::new(Status::InvalidArg, format!("PTY: unknown terminal mode {}", name)))
+}
+
+fn get_shell(shell_id: u32) -> Result<Arc<Shell>> {
+    SHELLS
+        .lock()
+        .get(&shell_id)
+        .cloned()
+        .ok_or_else(|| napi::Error::new(Status::InvalidArg, "Shell not found"))
+}
+
+// Opens an interactive shell on a PTY. Output (stdout and stderr alike, as a
+// terminal shows them) goes to `on_data`; `on_exit` fires once the channel
+// closes, after which the shell id is no longer valid.
+#[napi]
+pub async fn ssh_open_shell(
+    session_id: u32,
+    options: Option<ShellOptions>,
+    #[napi(ts_arg_type = "(chunk: Buffer) => void")] on_data: Callback<Buffer>,
+    #[napi(ts_arg_type = "(exit: ExecExit) => void")] on_exit: Callback<ExecExit>,
+) -> Result<u32> {
+    let options = options.unwrap_or_default();
+    let modes = options
+        .modes
+        .iter()
+        .flatten()
+        .map(|(name, value)| Ok((terminal_mode(name)?, *value)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let session = get_session(session_id)?;
+    let channel = exec::open(&session).await?;
+
+    channel
+        .request_pty(
+            true,
+            options.term.as_deref().unwrap_or(DEFAULT_TERM),
+            options.cols.unwrap_or(DEFAULT_COLS),
+            options.rows.unwrap_or(DEFAULT_ROWS),
+            0,
+            0,
+            &modes,
+        )
+        .await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("PTY: {}", e)))?;
+
+    channel.request_shell(true).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Shell: {}", e)))?;
+
+    let (mut reader, writer) = channel.split();
+    let shell_id = NEXT_SHELL_ID.fetch_add(1, Ordering::Relaxed);
+    SHELLS.lock().insert(shell_id, Arc::new(Shell { session_id, channel: writer }));
+
+    tokio::spawn(async move {
+        let mut exit = ExecExit::default();
+        let mut output = |output| {
+            let (Output::Stdout(data) | Output::Stderr(data)) = output;
+            on_data.call(data.into(), ThreadsafeFunctionCallMode::NonBlocking);
+        };
+        while let Some(msg) = reader.wait().await {
+            if !exec::read(msg, &mut exit, &mut output) {
+                break;
+            }
+        }
+        SHELLS.lock().remove(&shell_id);
+        on_exit.call(exit, ThreadsafeFunctionCallMode::NonBlocking);
+    });
+
+    Ok(shell_id)
+}
+
+#[napi]
+pub async fn ssh_shell_write(shell_id: u32, data: Buffer) -> Result<()> {
+    let data = data.to_vec();
+    let shell = get_shell(shell_id)?;
+    shell.channel.data(&data[..]).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Shell write: {}", e)))
+}
+
+#[napi]
+pub async fn ssh_shell_resize(shell_id: u32, cols: u32, rows: u32) -> Result<()> {
+    let shell = get_shell(shell_id)?;
+    shell.channel.window_change(cols, rows, 0, 0).await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Shell resize: {}", e)))
+}
+
+// Sends EOF and closes the channel; `on_exit` still fires when the server
+// acknowledges the close.
+#[napi]
+pub async fn ssh_shell_close(shell_id: u32) -> Result<()> {
+    let shell = get_shell(shell_id)?;
+    let _ = shell.channel.eof().await;
+    shell.channel.close().await
+        .map_err(|e| napi::Error::new(Status::GenericFailure, format!("Shell close: {}", e)))
+}
+
+pub(crate) fn count(session_id: u32) -> u32 {
+    SHELLS.lock().values().filter(|shell| shell.session_id == session_id).count() as u32
+}
+
+pub(crate) fn close_session(session_id: u32) {
+    SHELLS.lock().retain(|_, shell| shell.session_id != session_id);
+}
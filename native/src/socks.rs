@@ -0,0 +1,255 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4: u8 = 4;
+const SOCKS5: u8 = 5;
+const CMD_CONNECT: u8 = 1;
+
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS5_SUCCEEDED: u8 = 0x00;
+const SOCKS5_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    version: u8,
+}
+
+fn protocol_error(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads a SOCKS4, SOCKS4a or SOCKS5 CONNECT request. Only unauthenticated
+// clients are accepted, as with `ssh -D`.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Target> {
+    match stream.read_u8().await? {
+        SOCKS4 => accept_v4(stream).await,
+        SOCKS5 => accept_v5(stream).await,
+        version => Err(protocol_error(&format!("unsupported SOCKS version {}", version))),
+    }
+}
+
+async fn read_until_nul<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => return Ok(bytes),
+            _ if bytes.len() == 255 => return Err(protocol_error("SOCKS4 field too long")),
+            b => bytes.push(b),
+        }
+    }
+}
+
+async fn accept_v4<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Target> {
+    let command = stream.read_u8().await?;
+    let port = stream.read_u16().await?;
+    let mut ip = [0u8; 4];
+    stream.read_exact(&mut ip).await?;
+    read_until_nul(stream).await?;
+
+    // SOCKS4a marks a domain name with the address 0.0.0.x, x != 0.
+    let host = if ip[..3] == [0, 0, 0] && ip[3] != 0 {
+        String::from_utf8(read_until_nul(stream).await?)
+            .map_err(|_| protocol_error("SOCKS4a host is not valid UTF-8"))?
+    } else {
+        Ipv4Addr::from(ip).to_string()
+    };
+
+    let target = Target { host, port, version: SOCKS4 };
+    if command != CMD_CONNECT {
+        reply(stream, &target, false).await?;
+        return Err(protocol_error("only SOCKS CONNECT is supported"));
+    }
+    Ok(target)
+}
+
+async fn accept_v5<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Target> {
+    let count = stream.read_u8().await?;
+    let mut methods = vec![0u8; count as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        stream.write_all(&[SOCKS5, SOCKS5_NO_ACCEPTABLE_METHOD]).await?;
+        return Err(protocol_error("SOCKS5 client requires authentication"));
+    }
+    stream.write_all(&[SOCKS5, SOCKS5_NO_AUTH]).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _, address_type] = header;
+    if version != SOCKS5 {
+        return Err(protocol_error("bad SOCKS5 request"));
+    }
+
+    let host = match address_type {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut name = vec![0u8; len as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| protocol_error("SOCKS5 host is not valid UTF-8"))?
+        }
+        _ => {
+            send_v5(stream, SOCKS5_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(protocol_error("unsupported SOCKS5 address type"));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != CMD_CONNECT {
+        send_v5(stream, SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        return Err(protocol_error("only SOCKS CONNECT is supported"));
+    }
+    Ok(Target { host, port, version: SOCKS5 })
+}
+
+async fn send_v5<S: AsyncWrite + Unpin>(stream: &mut S, status: u8) -> Result<()> {
+    stream.write_all(&[SOCKS5, status, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+// Tells the client whether the channel to its target could be opened. The
+// bound address is not known on our side, so it is reported as 0.0.0.0:0.
+pub async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, target: &Target, connected: bool) -> Result<()> {
+    match target.version {
+        SOCKS4 => {
+            let status = if connected { SOCKS4_GRANTED } else { SOCKS4_REJECTED };
+            stream.write_all(&[0, status, 0, 0, 0, 0, 0, 0]).await
+        }
+        _ => send_v5(stream, if connected { SOCKS5_SUCCEEDED } else { SOCKS5_HOST_UNREACHABLE }).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    // Sends `request` and runs `accept` on the other end; returns its result
+    // and whatever it wrote back.
+    async fn accept_request(request: &[u8]) -> (Result<Target>, Vec<u8>) {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(request).await.unwrap();
+        client.shutdown().await.unwrap();
+        let target = accept(&mut server).await;
+        drop(server);
+        (target, read_all(&mut client).await)
+    }
+
+    async fn read_all(client: &mut DuplexStream) -> Vec<u8> {
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        written
+    }
+
+    #[tokio::test]
+    async fn socks4_ipv4() {
+        let (target, written) = accept_request(&[4, 1, 0, 22, 192, 0, 2, 1, b'u', 0]).await;
+        let target = target.unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("192.0.2.1", 22));
+        assert!(written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn socks4a_domain_after_user_id() {
+        let (target, _) = accept_request(b"\x04\x01\x01\xbb\x00\x00\x00\x07user\x00example.com\x00").await;
+        let target = target.unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("example.com", 443));
+    }
+
+    #[tokio::test]
+    async fn socks4_missing_nul_is_an_error() {
+        let (target, _) = accept_request(&[4, 1, 0, 22, 192, 0, 2, 1, b'u']).await;
+        assert_eq!(target.err().unwrap().kind(), ErrorKind::UnexpectedEof);
+
+        let mut request = vec![4, 1, 0, 22, 192, 0, 2, 1];
+        request.extend([b'u'; 300]);
+        let (target, _) = accept_request(&request).await;
+        assert_eq!(target.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn socks4_bind_is_rejected() {
+        let (target, written) = accept_request(&[4, 2, 0, 22, 192, 0, 2, 1, 0]).await;
+        assert!(target.is_err());
+        assert_eq!(written, [0, SOCKS4_REJECTED, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn socks5_domain() {
+        let (target, written) = accept_request(b"\x05\x01\x00\x05\x01\x00\x03\x0bexample.com\x00\x16").await;
+        let target = target.unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("example.com", 22));
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_AUTH]);
+    }
+
+    #[tokio::test]
+    async fn socks5_ipv4_and_ipv6() {
+        let (target, _) = accept_request(&[5, 1, 0, 5, 1, 0, 1, 192, 0, 2, 1, 0, 80]).await;
+        assert_eq!(target.unwrap().host, "192.0.2.1");
+
+        let mut request = vec![5, 1, 0, 5, 1, 0, 4];
+        request.extend(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        request.extend([0, 80]);
+        let (target, _) = accept_request(&request).await;
+        let target = target.unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("2001:db8::1", 80));
+    }
+
+    #[tokio::test]
+    async fn socks5_requires_no_auth_method() {
+        let (target, written) = accept_request(&[5, 1, 2]).await;
+        assert!(target.is_err());
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_ACCEPTABLE_METHOD]);
+    }
+
+    #[tokio::test]
+    async fn socks5_unsupported_command_and_address_type() {
+        let (target, written) = accept_request(&[5, 1, 0, 5, 3, 0, 1, 192, 0, 2, 1, 0, 80]).await;
+        assert!(target.is_err());
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_AUTH, SOCKS5, SOCKS5_COMMAND_NOT_SUPPORTED, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
+
+        let (target, written) = accept_request(&[5, 1, 0, 5, 1, 0, 9]).await;
+        assert!(target.is_err());
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_AUTH, SOCKS5, SOCKS5_ADDRESS_NOT_SUPPORTED, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn unknown_version_is_an_error() {
+        let (target, written) = accept_request(&[6]).await;
+        assert_eq!(target.err().unwrap().kind(), ErrorKind::InvalidData);
+        assert!(written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replies_per_version() {
+        let (mut client, mut server) = duplex(64);
+        let v4 = Target { host: String::new(), port: 0, version: SOCKS4 };
+        let v5 = Target { host: String::new(), port: 0, version: SOCKS5 };
+        reply(&mut server, &v4, true).await.unwrap();
+        reply(&mut server, &v5, false).await.unwrap();
+        drop(server);
+        assert_eq!(
+            read_all(&mut client).await,
+            [0, SOCKS4_GRANTED, 0, 0, 0, 0, 0, 0, SOCKS5, SOCKS5_HOST_UNREACHABLE, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use crate::callback::Callback;
+use crate::cancel;
+use crate::glob::Filter;
+use crate::integrity;
+use crate::sftp::{self, Sftp};
+use crate::transfer::{self, Settings, Throttle, TransferOptions};
+use crate::{get_session, Session};
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use russh_sftp::protocol::FileAttributes;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+
+// Files in flight at once; each also pipelines its own requests.
+const DEFAULT_CONCURRENCY: u32 = 4;
+const PERMISSION_BITS: u32 = 0o7777;
+
+#[napi(object, object_to_js = false)]
+#[derive(Default)]
+pub struct SyncOptions {
+    /// Globs over paths relative to the source directory: `*` and `?` stop at
+    /// "/", `**` does not, and a pattern without "/" matches names at any depth.
+    /// When given, only matching files are copied.
+    pub include: Option<Vec<String>>,
+    /// Matching files are skipped and matching directories are not descended into.
+    pub exclude: Option<Vec<String>>,
+    /// Copy permission bits and access/modification times to the destination.
+    pub preserve: Option<bool>,
+    /// Skip files that already match at the destination: "size-mtime" compares
+    /// size and modification time, "hash" compares size and SHA-256.
+    pub delta: Option<String>,
+    /// Files transferred at once; defaults to 4.
+    pub concurrency: Option<u32>,
+    /// Outstanding requests per file; defaults to 32.
+    pub requests: Option<u32>,
+    /// Token from `sshCreateCancelToken`, for `sshCancel`.
+    pub cancel_id: Option<u32>,
+    #[napi(ts_type = "(progress: SyncProgress) => void")]
+    pub on_progress: Option<Callback<SyncProgress>>,
+}
+
+#[napi(object)]
+pub struct SyncProgress {
+    /// Files transferred or skipped so far.
+    pub files_done: u32,
+    pub files_total: u32,
+    pub bytes_done: i64,
+    pub bytes_total: i64,
+}
+
+#[napi(object)]
+pub struct SyncResult {
+    pub files_transferred: u32,
+    /// Files left alone because delta mode found them unchanged.
+    pub files_skipped: u32,
+    pub bytes_transferred: i64,
+    pub directories_created: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Upload,
+    Download,
+}
+
+#[derive(Clone, Copy)]
+enum Delta {
+    SizeMtime,
+    Hash,
+}
+
+fn delta(mode: Option<&str>) -> Result<Option<Delta>> {
+    match mode {
+        None => Ok(None),
+        Some("size-mtime") => Ok(Some(Delta::SizeMtime)),
+        Some("hash") => Ok(Some(Delta::Hash)),
+        Some(other) => Err(napi::Error::new(Status::InvalidArg, format!("Sync: unknown delta mode {}", other))),
+    }
+}
+
+// A file or directory under the source root; `mode` holds permission bits only.
+struct Entry {
+    relative: String,
+    size: u64,
+    mode: Option<u32>,
+    atime: Option<u32>,
+    mtime: Option<u32>,
+}
+
+impl Entry {
+    fn local(relative: String, metadata: &std::fs::Metadata) -> Entry {
+        Entry {
+            relative,
+            size: metadata.len(),
+            mode: local_mode(metadata),
+            atime: seconds(metadata.accessed()),
+            mtime: seconds(metadata.modified()),
+        }
+    }
+
+    fn remote(relative: String, attrs: &FileAttributes) -> Entry {
+        Entry {
+            relative,
+            size: attrs.size.unwrap_or(0),
+            mode: attrs.permissions.map(|mode| mode & PERMISSION_BITS),
+            atime: attrs.atime,
+            mtime: attrs.mtime,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & PERMISSION_BITS)
+}
+
+#[cfg(not(unix))]
+fn local_mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+fn seconds(time: std::io::Result<SystemTime>) -> Option<u32> {
+    Some(time.ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs() as u32)
+}
+
+fn child(parent: &str, name: &str) -> String {
+    if parent.is_empty() { name.to_string() } else { format!("{}/{}", parent, name) }
+}
+
+fn remote_path(root: &str, relative: &str) -> String {
+    if relative.is_empty() { root.to_string() } else { format!("{}/{}", root.trim_end_matches('/'), relative) }
+}
+
+// Names come from the server, so one that is not a single path component
+// could place a download outside the local directory.
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+fn local_path(root: &Path, relative: &str) -> Result<PathBuf> {
+    let path = root.join(relative);
+    if Path::new(relative).components().all(|c| matches!(c, Component::Normal(_))) && path.starts_with(root) {
+        Ok(path)
+    } else {
+        Err(napi::Error::new(Status::GenericFailure, format!("Sync: {}: outside the local directory", relative)))
+    }
+}
+
+// Directories come back parents first, the root itself included with an
+// empty relative path. Symlinks and special files are skipped.
+async fn walk_local(root: &Path, filter: &Filter) -> Result<(Vec<Entry>, Vec<Entry>)> {
+    let metadata = tokio::fs::metadata(root).await
+        .map_err(|e| transfer::local_error("Read dir", e))?;
+    let mut dirs = vec![Entry::local(String::new(), &metadata)];
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+
+    while let Some(dir) = pending.pop() {
+        let mut reader = tokio::fs::read_dir(root.join(&dir)).await
+            .map_err(|e| transfer::local_error("Read dir", e))?;
+        while let Some(item) = reader.next_entry().await.map_err(|e| transfer::local_error("Read dir", e))? {
+            let relative = child(&dir, &item.file_name().to_string_lossy());
+            let metadata = item.metadata().await
+                .map_err(|e| transfer::local_error("Read dir", e))?;
+            if metadata.is_dir() && filter.directory(&relative) {
+                dirs.push(Entry::local(relative.clone(), &metadata));
+                pending.push(relative);
+            } else if metadata.is_file() && filter.file(&relative) {
+                files.push(Entry::local(relative, &metadata));
+            }
+        }
+    }
+    Ok((dirs, files))
+}
+
+async fn walk_remote(sftp: &Sftp, root: &str, filter: &Filter) -> Result<(Vec<Entry>, Vec<Entry>)> {
+    let attrs = sftp.stat(root).await?
+        .filter(|attrs| sftp::file_type(attrs) == sftp::S_IFDIR)
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, format!("Read dir: {}: Not a directory", root)))?;
+    let mut dirs = vec![Entry::remote(String::new(), &attrs)];
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+
+    while let Some(dir) = pending.pop() {
+        let path = remote_path(root, &dir);
+        for item in sftp.readdir(&path).await? {
+            if !safe_name(&item.filename) {
+                return Err(napi::Error::new(
+                    Status::GenericFailure,
+                    format!("Read dir: {}: unsafe entry name {:?}", path, item.filename),
+                ));
+            }
+            let relative = child(&dir, &item.filename);
+            match sftp::file_type(&item.attrs) {
+                sftp::S_IFDIR if filter.directory(&relative) => {
+                    dirs.push(Entry::remote(relative.clone(), &item.attrs));
+                    pending.push(relative);
+                }
+                sftp::S_IFREG if filter.file(&relative) => files.push(Entry::remote(relative, &item.attrs)),
+                _ => {}
+            }
+        }
+    }
+    Ok((dirs, files))
+}
+
+// Totals across every file of a sync, reported to JS at a limited rate.
+struct Progress {
+    callback: Option<Callback<SyncProgress>>,
+    files_total: u32,
+    bytes_total: u64,
+    files_done: AtomicU32,
+    bytes_done: AtomicU64,
+    throttle: parking_lot::Mutex<Throttle>,
+}
+
+impl Progress {
+    fn add(&self, files: u32, bytes: u64) {
+        self.files_done.fetch_add(files, Ordering::Relaxed);
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+        if self.throttle.lock().ready() {
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        let Some(callback) = &self.callback else { return };
+        callback.call(
+            SyncProgress {
+                files_done: self.files_done.load(Ordering::Relaxed),
+                files_total: self.files_total,
+                bytes_done: self.bytes_done.load(Ordering::Relaxed) as i64,
+                bytes_total: self.bytes_total as i64,
+            },
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+}
+
+struct Context {
+    session: Arc<Session>,
+    sftp: Arc<Sftp>,
+    direction: Direction,
+    local_root: PathBuf,
+    remote_root: String,
+    settings: Settings,
+    delta: Option<Delta>,
+    preserve: bool,
+    progress: Progress,
+}
+
+impl Context {
+    // Resolves with the bytes moved, or None when delta mode skipped the file.
+    async fn copy(&self, entry: Entry, mut token: Option<cancel::Token>) -> Result<Option<u64>> {
+        if token.as_ref().is_some_and(|token| token.borrow().is_some()) {
+            return Err(transfer::cancelled());
+        }
+
+        let local = local_path(&self.local_root, &entry.relative)?;
+        let remote = remote_path(&self.remote_root, &entry.relative);
+        if self.unchanged(&entry, &local, &remote).await? {
+            self.progress.add(1, entry.size);
+            return Ok(None);
+        }
+
+        let mut seen = 0;
+        let mut on_progress = |transferred: u64| {
+            self.progress.add(0, transferred.saturating_sub(seen));
+            seen = seen.max(transferred);
+        };
+        let local_path = local.to_string_lossy();
+        let result = match self.direction {
+            Direction::Upload => {
+                transfer::upload(&self.session, &self.sftp, &local_path, &remote, &self.settings, token.as_mut(), &mut on_progress).await?
+            }
+            Direction::Download => {
+                transfer::download(&self.session, &self.sftp, &remote, &local_path, &self.settings, token.as_mut(), &mut on_progress).await?
+            }
+        };
+        if let Some(mismatch) = &result.mismatch {
+            return Err(integrity::mismatch(&remote, mismatch));
+        }
+
+        if self.preserve {
+            self.set_attributes(&entry, &local, &remote, false).await?;
+        }
+        self.progress.add(1, entry.size.saturating_sub(seen));
+        Ok(Some(result.transferred as u64))
+    }
+
+    async fn unchanged(&self, entry: &Entry, local: &Path, remote: &str) -> Result<bool> {
+        let Some(delta) = self.delta else { return Ok(false) };
+
+        // Size and mtime of the copy already at the destination, if any.
+        let existing = match self.direction {
+            Direction::Upload => self.sftp.stat(remote).await?
+                .filter(|attrs| sftp::file_type(attrs) == sftp::S_IFREG)
+                .map(|attrs| (attrs.size.unwrap_or(0), attrs.mtime)),
+            Direction::Download => tokio::fs::metadata(local).await
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| (metadata.len(), seconds(metadata.modified()))),
+        };
+        let Some((_, mtime)) = existing.filter(|(size, _)| *size == entry.size) else {
+            return Ok(false);
+        };
+
+        match delta {
+            Delta::SizeMtime => Ok(mtime.is_some() && mtime == entry.mtime),
+            Delta::Hash => {
+                let local = integrity::file_sha256(local).await
+                    .map_err(|e| transfer::local_error("Read file", e))?;
+                let (remote, _) = integrity::remote_sha256(&self.session, &self.sftp, remote).await?;
+                Ok(local == remote)
+            }
+        }
+    }
+
+    async fn set_attributes(&self, entry: &Entry, local: &Path, remote: &str, directory: bool) -> Result<()> {
+        match self.direction {
+            Direction::Upload => {
+                // SFTP sets both times together, so a missing atime becomes the mtime.
+                let attrs = FileAttributes {
+                    permissions: entry.mode,
+                    atime: entry.mtime.and(entry.atime.or(entry.mtime)),
+                    mtime: entry.mtime,
+                    ..FileAttributes::empty()
+                };
+                self.sftp.raw.setstat(remote, attrs).await
+                    .map(|_| ())
+                    .map_err(|e| self.sftp.fail("Setstat", e))
+            }
+            Direction::Download => set_local_attributes(local, entry, directory)
+                .map_err(|e| transfer::local_error("Set attributes", e)),
+        }
+    }
+}
+
+fn set_local_attributes(path: &Path, entry: &Entry, directory: bool) -> std::io::Result<()> {
+    // Times first: a read-only mode would stop the file being opened for them.
+    if let Some(mtime) = entry.mtime {
+        let time = |seconds: u32| UNIX_EPOCH + Duration::from_secs(seconds as u64);
+        let times = std::fs::FileTimes::new()
+            .set_modified(time(mtime))
+            .set_accessed(time(entry.atime.unwrap_or(mtime)));
+        let file = if directory {
+            std::fs::File::open(path)?
+        } else {
+            std::fs::File::options().write(true).open(path)?
+        };
+        file.set_times(times)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+// Copies every file under `localDir` that passes the filters to the same
+// relative path under `remoteDir`, creating directories as needed. Files
+// share the session's SFTP channel, several at a time.
+#[napi]
+pub async fn ssh_upload_dir(
+    session_id: u32,
+    local_dir: String,
+    remote_dir: String,
+    options: Option<SyncOptions>,
+) -> Result<SyncResult> {
+    sync(session_id, Direction::Upload, local_dir, remote_dir, options.unwrap_or_default()).await
+}
+
+#[napi]
+pub async fn ssh_download_dir(
+    session_id: u32,
+    remote_dir: String,
+    local_dir: String,
+    options: Option<SyncOptions>,
+) -> Result<SyncResult> {
+    sync(session_id, Direction::Download, local_dir, remote_dir, options.unwrap_or_default()).await
+}
+
+async fn sync(
+    session_id: u32,
+    direction: Direction,
+    local_dir: String,
+    remote_dir: String,
+    options: SyncOptions,
+) -> Result<SyncResult> {
+    let cancel_id = options.cancel_id;
+    let token = cancel::subscribe(cancel_id);
+    let result = run(session_id, direction, local_dir, remote_dir, options, token).await;
+    cancel::release(cancel_id);
+    result
+}
+
+async fn run(
+    session_id: u32,
+    direction: Direction,
+    local_dir: String,
+    remote_dir: String,
+    options: SyncOptions,
+    token: Option<cancel::Token>,
+) -> Result<SyncResult> {
+    let SyncOptions { include, exclude, preserve, delta: delta_mode, concurrency, requests, on_progress, .. } = options;
+    let filter = Filter::new(&include.unwrap_or_default(), &exclude.unwrap_or_default());
+    let delta = delta(delta_mode.as_deref())?;
+    let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1) as usize;
+
+    let session = get_session(session_id)?;
+    let sftp = sftp::get(&session).await?;
+    let local_root = PathBuf::from(local_dir);
+
+    let (dirs, files) = match direction {
+        Direction::Upload => walk_local(&local_root, &filter).await?,
+        Direction::Download => walk_remote(&sftp, &remote_dir, &filter).await?,
+    };
+
+    let mut directories_created = 0;
+    for dir in &dirs {
+        let created = match direction {
+            Direction::Upload => {
+                let path = remote_path(&remote_dir, &dir.relative);
+                let missing = sftp.stat(&path).await?.is_none();
+                if missing {
+                    sftp.mkdir_all(&path, None).await?;
+                }
+                missing
+            }
+            Direction::Download => {
+                let path = local_path(&local_root, &dir.relative)?;
+                let missing = !path.is_dir();
+                if missing {
+                    tokio::fs::create_dir_all(&path).await
+                        .map_err(|e| transfer::local_error("Create dir", e))?;
+                }
+                missing
+            }
+        };
+        directories_created += created as u32;
+    }
+
+    let context = Arc::new(Context {
+        session,
+        sftp,
+        direction,
+        local_root,
+        remote_root: remote_dir,
+        settings: Settings::new(&TransferOptions { requests, ..Default::default() }),
+        delta,
+        preserve: preserve.unwrap_or(false),
+        progress: Progress {
+            callback: on_progress,
+            files_total: files.len() as u32,
+            bytes_total: files.iter().map(|file| file.size).sum(),
+            files_done: AtomicU32::new(0),
+            bytes_done: AtomicU64::new(0),
+            throttle: Default::default(),
+        },
+    });
+
+    let mut result = SyncResult { files_transferred: 0, files_skipped: 0, bytes_transferred: 0, directories_created };
+    let mut files = files.into_iter();
+    let mut tasks = JoinSet::new();
+    let mut failure = None;
+
+    // After a failure no new files start, but those in flight run to the end
+    // so that none is left half written.
+    loop {
+        while failure.is_none() && tasks.len() < concurrency {
+            let Some(entry) = files.next() else { break };
+            let (context, token) = (context.clone(), token.clone());
+            tasks.spawn(async move { context.copy(entry, token).await });
+        }
+
+        match tasks.join_next().await {
+            Some(Ok(Ok(Some(bytes)))) => {
+                result.files_transferred += 1;
+                result.bytes_transferred += bytes as i64;
+            }
+            Some(Ok(Ok(None))) => result.files_skipped += 1,
+            Some(Ok(Err(e))) => {
+                failure.get_or_insert(e);
+            }
+            Some(Err(e)) => {
+                failure.get_or_insert(transfer::join_error(e));
+            }
+            None => break,
+        }
+    }
+
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+
+    // Directory times change as files land in them, so they are set last,
+    // children before parents.
+    if context.preserve {
+        for dir in dirs.iter().rev() {
+            let local = local_path(&context.local_root, &dir.relative)?;
+            let remote = remote_path(&context.remote_root, &dir.relative);
+            context.set_attributes(dir, &local, &remote, true).await?;
+        }
+    }
+
+    context.progress.report();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_names_that_are_not_one_component() {
+        for name in ["", ".", "..", "a/b", "../etc", "a\0b"] {
+            assert!(!safe_name(name), "{:?}", name);
+        }
+        for name in ["a", "..a", "a..", ".hidden", "a b"] {
+            assert!(safe_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_local_paths_under_the_root() {
+        let root = Path::new("/tmp/sync-root");
+        assert_eq!(local_path(root, "").unwrap(), root);
+        assert_eq!(local_path(root, "a/b.txt").unwrap(), root.join("a/b.txt"));
+        for relative in ["..", "a/../../b", "/etc/passwd"] {
+            assert!(local_path(root, relative).is_err(), "{:?}", relative);
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use crate::callback::Callback;
+use crate::cancel;
+use crate::{get_session, Session};
+use crate::integrity;
+use crate::sftp::{self, Sftp};
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi_derive::napi;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+// Outstanding SFTP requests per transfer, as with `sftp -R`.
+const DEFAULT_REQUESTS: u32 = 32;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[napi(object, object_to_js = false)]
+#[derive(Default)]
+pub struct TransferOptions {
+    /// Continue from the destination's current size instead of starting over.
+    pub resume: Option<bool>,
+    /// Outstanding read or write requests; defaults to 32.
+    pub requests: Option<u32>,
+    /// Token from `sshCreateCancelToken`, for `sshCancel`.
+    pub cancel_id: Option<u32>,
+    /// Hash the data while streaming and compare it with the remote file's
+    /// SHA-256 afterwards; a mismatch is reported in `mismatch`.
+    pub verify: Option<bool>,
+    /// Uploads only: write a temporary sibling, fsync it and rename it over
+    /// the target, so the target is never seen half written.
+    pub atomic: Option<bool>,
+    /// With `atomic`, give the new file the permissions of the one it replaces.
+    pub keep_permissions: Option<bool>,
+    #[napi(ts_type = "(progress: TransferProgress) => void")]
+    pub on_progress: Option<Callback<TransferProgress>>,
+}
+
+#[napi(object)]
+pub struct TransferProgress {
+    /// Bytes at the destination so far, a resumed prefix included.
+    pub transferred: i64,
+    pub total: i64,
+}
+
+#[napi(object)]
+pub struct TransferResult {
+    pub size: i64,
+    /// Bytes moved by this call.
+    pub transferred: i64,
+    /// Where a resumed transfer picked up; 0 when it started from scratch.
+    pub resumed_from: i64,
+    /// SHA-256 of the whole file, when verified.
+    pub sha256: Option<String>,
+    /// How the remote hash was obtained: "check-file" or "sha256sum".
+    pub verified_with: Option<String>,
+    /// Set when verification found the two copies differ. An upload then
+    /// removes what it wrote, so an atomic one leaves the target untouched;
+    /// a download keeps the local copy.
+    pub mismatch: Option<IntegrityMismatch>,
+}
+
+#[napi(object)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntegrityMismatch {
+    pub local_sha256: String,
+    pub remote_sha256: String,
+}
+
+pub(crate) struct Settings {
+    pub resume: bool,
+    pub requests: usize,
+    pub verify: bool,
+    pub atomic: bool,
+    pub keep_permissions: bool,
+}
+
+impl Settings {
+    pub fn new(options: &TransferOptions) -> Self {
+        Settings {
+            resume: options.resume.unwrap_or(false),
+            requests: options.requests.unwrap_or(DEFAULT_REQUESTS).max(1) as usize,
+            verify: options.verify.unwrap_or(false),
+            atomic: options.atomic.unwrap_or(false),
+            keep_permissions: options.keep_permissions.unwrap_or(false),
+        }
+    }
+}
+
+// Told the bytes at the destination so far after every acknowledged chunk.
+pub(crate) type OnProgress<'a> = &'a mut (dyn FnMut(u64) + Send);
+
+// Limits progress events so that small chunks do not flood the JS thread.
+#[derive(Default)]
+pub(crate) struct Throttle {
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn ready(&mut self) -> bool {
+        if self.last.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            return false;
+        }
+        self.last = Some(Instant::now());
+        true
+    }
+}
+
+fn report(callback: &Callback<TransferProgress>, transferred: u64, total: u64) {
+    callback.call(
+        TransferProgress { transferred: transferred as i64, total: total as i64 },
+        ThreadsafeFunctionCallMode::NonBlocking,
+    );
+}
+
+// Writes can be acknowledged out of order; this tracks how far the
+// destination is complete without gaps.
+struct Acked {
+    contiguous: u64,
+    ahead: BTreeMap<u64, u64>,
+}
+
+impl Acked {
+    fn add(&mut self, offset: u64, len: u64) {
+        self.ahead.insert(offset, len);
+        while let Some(len) = self.ahead.remove(&self.contiguous) {
+            self.contiguous += len;
+        }
+    }
+}
+
+pub(crate) fn local_error(context: &str, e: std::io::Error) -> napi::Error {
+    napi::Error::new(Status::GenericFailure, format!("{}: {}", context, e))
+}
+
+pub(crate) fn cancelled() -> napi::Error {
+    napi::Error::new(Status::Cancelled, "Transfer: cancelled")
+}
+
+pub(crate) fn join_error(e: tokio::task::JoinError) -> napi::Error {
+    napi::Error::new(Status::GenericFailure, format!("Transfer: {}", e))
+}
+
+// Streams the file in chunks with several writes in flight. A failed or
+// cancelled upload leaves the remote file holding a gap-free prefix, so it
+// can be resumed; in atomic mode that prefix is in the temporary file and the
+// target is untouched.
+#[napi]
+pub async fn ssh_upload_file(
+    session_id: u32,
+    local_path: String,
+    remote_path: String,
+    options: Option<TransferOptions>,
+) -> Result<TransferResult> {
+    let options = options.unwrap_or_default();
+    let settings = Settings::new(&options);
+    let TransferOptions { cancel_id, on_progress, .. } = options;
+    let mut token = cancel::subscribe(cancel_id);
+    let mut throttle = Throttle::default();
+    let total = AtomicU64::new(0);
+    let mut progress = |transferred| {
+        if let Some(callback) = on_progress.as_ref().filter(|_| throttle.ready()) {
+            report(callback, transferred, total.load(Ordering::Relaxed));
+        }
+    };
+
+    let result = async {
+        let session = get_session(session_id)?;
+        let sftp = sftp::get(&session).await?;
+        let size = tokio::fs::metadata(&local_path).await
+            .map_err(|e| local_error("Read file", e))?
+            .len();
+        total.store(size, Ordering::Relaxed);
+        upload(&session, &sftp, &local_path, &remote_path, &settings, token.as_mut(), &mut progress).await
+    }
+    .await;
+
+    cancel::release(cancel_id);
+    if let (Ok(result), Some(callback)) = (&result, &on_progress) {
+        report(callback, result.size as u64, result.size as u64);
+    }
+    result
+}
+
+pub(crate) async fn upload(
+    session: &Session,
+    sftp: &Arc<Sftp>,
+    local_path: &str,
+    remote_path: &str,
+    settings: &Settings,
+    token: Option<&mut cancel::Token>,
+    progress: OnProgress<'_>,
+) -> Result<TransferResult> {
+    if !settings.atomic {
+        let result = write_remote(session, sftp, local_path, remote_path, settings, token, progress).await?;
+        if result.mismatch.is_some() {
+            let _ = sftp.raw.remove(remote_path).await;
+        }
+        return Ok(result);
+    }
+
+    // A fixed name lets a resumed atomic upload continue where the last one stopped.
+    let temp_path = match remote_path.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/.{}.part", dir, name),
+        None => format!(".{}.part", remote_path),
+    };
+
+    let result = async {
+        let result = write_remote(session, sftp, local_path, &temp_path, settings, token, progress).await?;
+        if result.mismatch.is_some() {
+            // Resuming would only reproduce the bad copy.
+            let _ = sftp.raw.remove(temp_path.as_str()).await;
+            return Ok(result);
+        }
+        if settings.keep_permissions {
+            if let Some(mode) = sftp.stat(remote_path).await?.and_then(|attrs| attrs.permissions) {
+                sftp.raw.setstat(temp_path.as_str(), sftp::attributes(Some(mode & 0o7777))).await
+                    .map_err(|e| sftp.fail("Setstat", e))?;
+            }
+        }
+        // Without posix-rename the target has to go first, which leaves a
+        // short window in which it is missing.
+        if !sftp.supports(sftp::POSIX_RENAME) && sftp.stat(remote_path).await?.is_some() {
+            sftp.raw.remove(remote_path).await
+                .map_err(|e| sftp.fail("Remove", e))?;
+        }
+        sftp.rename(&temp_path, remote_path).await?;
+        Ok(result)
+    }
+    .await;
+
+    if result.is_err() && !settings.resume {
+        let _ = sftp.raw.remove(temp_path).await;
+    }
+    result
+}
+
+async fn write_remote(
+    session: &Session,
+    sftp: &Arc<Sftp>,
+    local_path: &str,
+    remote_path: &str,
+    settings: &Settings,
+    mut token: Option<&mut cancel::Token>,
+    progress: OnProgress<'_>,
+) -> Result<TransferResult> {
+    let mut file = tokio::fs::File::open(local_path).await
+        .map_err(|e| local_error("Read file", e))?;
+    let size = file.metadata().await
+        .map_err(|e| local_error("Read file", e))?
+        .len();
+
+    let mut start = 0;
+    if settings.resume {
+        if let Some(attrs) = sftp.stat(remote_path).await? {
+            start = attrs.size.filter(|&remote| remote <= size).unwrap_or(0);
+        }
+    }
+
+    let flags = if start > 0 { OpenFlags::WRITE } else { OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE };
+    let handle = sftp.raw.open(remote_path, flags, FileAttributes::empty()).await
+        .map_err(|e| sftp.fail("Open", e))?
+        .handle;
+
+    let mut hasher = settings.verify.then(Sha256::new);
+    if let Some(hasher) = hasher.as_mut() {
+        integrity::hash_prefix(&mut file, start, hasher).await
+            .map_err(|e| local_error("Read file", e))?;
+    }
+    file.seek(SeekFrom::Start(start)).await
+        .map_err(|e| local_error("Read file", e))?;
+
+    let mut acked = Acked { contiguous: start, ahead: BTreeMap::new() };
+    let mut writes = JoinSet::new();
+    let mut offset = start;
+    let mut failure = None;
+
+    loop {
+        while failure.is_none() && offset < size && writes.len() < settings.requests {
+            let len = (size - offset).min(sftp.write_len as u64);
+            let mut chunk = vec![0; len as usize];
+            if let Err(e) = file.read_exact(&mut chunk).await {
+                failure = Some(local_error("Read file", e));
+                break;
+            }
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            let (sftp, handle, at) = (sftp.clone(), handle.clone(), offset);
+            writes.spawn(async move { sftp.raw.write(handle, at, chunk).await.map(|_| (at, len)) });
+            offset += len;
+        }
+
+        // In-flight writes are always drained, even after a failure, so that
+        // nothing lands behind the truncation below.
+        tokio::select! {
+            written = writes.join_next() => match written {
+                Some(Ok(Ok((at, len)))) => {
+                    acked.add(at, len);
+                    progress(acked.contiguous);
+                }
+                Some(Ok(Err(e))) => {
+                    let e = sftp.fail("Write", e);
+                    failure.get_or_insert(e);
+                }
+                Some(Err(e)) => {
+                    failure.get_or_insert(join_error(e));
+                }
+                None => break,
+            },
+            _ = cancel::cancelled(token.as_deref_mut()), if failure.is_none() => failure = Some(cancelled()),
+        }
+    }
+
+    if let Some(failure) = failure {
+        if !acked.ahead.is_empty() {
+            let attrs = FileAttributes { size: Some(acked.contiguous), ..FileAttributes::empty() };
+            let _ = sftp.raw.fsetstat(handle.as_str(), attrs).await;
+        }
+        let _ = sftp.raw.close(handle).await;
+        return Err(failure);
+    }
+
+    // Only the atomic rename depends on the data being on disk first.
+    if settings.atomic && sftp.supports(russh_sftp::extensions::FSYNC) {
+        if let Err(e) = sftp.raw.fsync(handle.as_str()).await {
+            let e = sftp.fail("Fsync", e);
+            let _ = sftp.raw.close(handle).await;
+            return Err(e);
+        }
+    }
+
+    sftp.raw.close(handle).await
+        .map_err(|e| sftp.fail("Close", e))?;
+
+    let mut result = TransferResult {
+        size: size as i64,
+        transferred: (size - start) as i64,
+        resumed_from: start as i64,
+        sha256: None,
+        verified_with: None,
+        mismatch: None,
+    };
+    if let Some(hasher) = hasher {
+        verify_remote(session, sftp, remote_path, integrity::hex(hasher), &mut result).await?;
+    }
+    Ok(result)
+}
+
+async fn verify_remote(
+    session: &Session,
+    sftp: &Sftp,
+    remote_path: &str,
+    local: String,
+    result: &mut TransferResult,
+) -> Result<()> {
+    let (remote, method) = integrity::remote_sha256(session, sftp, remote_path).await?;
+    result.verified_with = Some(method.to_string());
+    match integrity::compare(local, remote) {
+        Ok(sha256) => result.sha256 = Some(sha256),
+        Err(mismatch) => result.mismatch = Some(mismatch),
+    }
+    Ok(())
+}
+
+// Streams the remote file with several reads in flight, writing them to disk
+// in order, so an interrupted download is always a valid prefix to resume.
+#[napi]
+pub async fn ssh_download_file(
+    session_id: u32,
+    remote_path: String,
+    local_path: String,
+    options: Option<TransferOptions>,
+) -> Result<TransferResult> {
+    let options = options.unwrap_or_default();
+    let settings = Settings::new(&options);
+    let TransferOptions { cancel_id, on_progress, .. } = options;
+    let mut token = cancel::subscribe(cancel_id);
+    let mut throttle = Throttle::default();
+    let total = AtomicU64::new(0);
+    let mut progress = |transferred| {
+        if let Some(callback) = on_progress.as_ref().filter(|_| throttle.ready()) {
+            report(callback, transferred, total.load(Ordering::Relaxed));
+        }
+    };
+
+    let result = async {
+        let session = get_session(session_id)?;
+        let sftp = sftp::get(&session).await?;
+        let size = sftp.stat(&remote_path).await?.and_then(|attrs| attrs.size).unwrap_or(0);
+        total.store(size, Ordering::Relaxed);
+        download(&session, &sftp, &remote_path, &local_path, &settings, token.as_mut(), &mut progress).await
+    }
+    .await;
+
+    cancel::release(cancel_id);
+    if let (Ok(result), Some(callback)) = (&result, &on_progress) {
+        report(callback, result.size as u64, result.size as u64);
+    }
+    result
+}
+
+pub(crate) async fn download(
+    session: &Session,
+    sftp: &Arc<Sftp>,
+    remote_path: &str,
+    local_path: &str,
+    settings: &Settings,
+    mut token: Option<&mut cancel::Token>,
+    progress: OnProgress<'_>,
+) -> Result<TransferResult> {
+    let size = sftp.stat(remote_path).await?
+        .ok_or_else(|| napi::Error::new(Status::GenericFailure, format!("Stat: {}: No such file", remote_path)))?
+        .size
+        .unwrap_or(0);
+
+    let mut start = 0;
+    if settings.resume {
+        if let Ok(metadata) = tokio::fs::metadata(local_path).await {
+            start = Some(metadata.len()).filter(|&local| local <= size).unwrap_or(0);
+        }
+    }
+
+    let mut hasher = settings.verify.then(Sha256::new);
+    if let Some(hasher) = hasher.as_mut().filter(|_| start > 0) {
+        let mut existing = tokio::fs::File::open(local_path).await
+            .map_err(|e| local_error("Read file", e))?;
+        integrity::hash_prefix(&mut existing, start, hasher).await
+            .map_err(|e| local_error("Read file", e))?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(start == 0)
+        .open(local_path)
+        .await
+        .map_err(|e| local_error("Write file", e))?;
+    file.seek(SeekFrom::Start(start)).await
+        .map_err(|e| local_error("Write file", e))?;
+
+    let handle = sftp.raw.open(remote_path, OpenFlags::READ, FileAttributes::empty()).await
+        .map_err(|e| sftp.fail("Open", e))?
+        .handle;
+
+    let read = |reads: &mut JoinSet<_>, at: u64, len: u64| {
+        let (sftp, handle) = (sftp.clone(), handle.clone());
+        reads.spawn(async move { (at, len, sftp.raw.read(handle, at, len as u32).await) });
+    };
+
+    let mut reads = JoinSet::new();
+    let mut ahead = BTreeMap::new();
+    let mut written = start;
+    let mut offset = start;
+    // Lowered if the file turns out to be shorter than its stat said.
+    let mut end = size;
+    let mut failure = None;
+
+    loop {
+        while failure.is_none() && offset < end && reads.len() < settings.requests {
+            let len = (end - offset).min(sftp.read_len as u64);
+            read(&mut reads, offset, len);
+            offset += len;
+        }
+
+        tokio::select! {
+            chunk = reads.join_next() => match chunk {
+                Some(Ok((at, len, Ok(data)))) => {
+                    let got = data.data.len() as u64;
+                    // Servers may answer with less than asked for; ask again for the rest.
+                    if got == 0 {
+                        end = end.min(at);
+                        continue;
+                    }
+                    if got < len && failure.is_none() {
+                        read(&mut reads, at + got, len - got);
+                    }
+                    ahead.insert(at, data.data);
+                    while let Some(data) = ahead.remove(&written) {
+                        if let Err(e) = file.write_all(&data).await {
+                            failure.get_or_insert(local_error("Write file", e));
+                            break;
+                        }
+                        if let Some(hasher) = hasher.as_mut() {
+                            hasher.update(&data);
+                        }
+                        written += data.len() as u64;
+                    }
+                    progress(written);
+                }
+                Some(Ok((at, _, Err(e)))) if sftp::is_status(&e, StatusCode::Eof) => end = end.min(at),
+                Some(Ok((_, _, Err(e)))) => {
+                    let e = sftp.fail("Read", e);
+                    failure.get_or_insert(e);
+                }
+                Some(Err(e)) => {
+                    failure.get_or_insert(join_error(e));
+                }
+                None => break,
+            },
+            _ = cancel::cancelled(token.as_deref_mut()), if failure.is_none() => failure = Some(cancelled()),
+        }
+    }
+
+    let _ = sftp.raw.close(handle).await;
+    file.flush().await
+        .map_err(|e| local_error("Write file", e))?;
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+
+    // Chunks left past a gap, or a file that ended before its stat'd size,
+    // mean a short copy; it only counts as complete if the file really shrank
+    // to what was written.
+    if !ahead.is_empty() || written < size {
+        let now = sftp.stat(remote_path).await?.and_then(|attrs| attrs.size);
+        if !ahead.is_empty() || now != Some(written) {
+            return Err(napi::Error::new(
+                Status::GenericFailure,
+                format!("Read: {}: ended at {} of {} bytes", remote_path, written, size),
+            ));
+        }
+    }
+
+    let mut result = TransferResult {
+        size: written as i64,
+        transferred: (written - start) as i64,
+        resumed_from: start as i64,
+        sha256: None,
+        verified_with: None,
+        mismatch: None,
+    };
+    if let Some(hasher) = hasher {
+        verify_remote(session, sftp, remote_path, integrity::hex(hasher), &mut result).await?;
+    }
+    Ok(result)
+}
@@ -11,8 +11,9 @@ import Log from './common/logger';
 import SSHDestination from './ssh/sshDestination';
 import SSHConnection, { SSHTunnelConfig } from './ssh/sshConnection';
 import { NativeSSHConnection } from './nativeSSHConnection';
+import * as NativeSSH from './native/ssh';
 import SSHConfiguration from './ssh/sshConfig';
-import { gatherIdentityFiles } from './ssh/identityFiles';
+import { gatherIdentityFiles, nativeIdentities } from './ssh/identityFiles';
 import { untildify, exists as fileExists } from './common/files';
 import { findRandomPort } from './common/ports';
 import { disposeAll } from './common/disposable';
@@ -75,6 +76,10 @@ function splitShellArgs(value: string): string[] {
     return args;
 }
 
+function proxyCommandLine(value: string | string[]): string {
+    return Array.isArray(value) ? value.join(' ') : String(value);
+}
+
 function stripWrappingQuotes(value: string): string {
     if (value.length < 2) {
         return value;
@@ -148,14 +153,15 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
         const defaultExtensions = remoteSSHconfig.get<string[]>('defaultExtensions', []);
         const remotePlatformMap = remoteSSHconfig.get<Record<string, string>>('remotePlatform', {});
         const remoteServerListenOnSocket = remoteSSHconfig.get<boolean>('remoteServerListenOnSocket', false)!;
+        const reconnect = remoteSSHconfig.get<boolean>('reconnect', false)!;
         const connectTimeout = remoteSSHconfig.get<number>('connectTimeout', 60)!;
         const resolvedArchivePath = serverArchivePath ? untildify(serverArchivePath) : undefined;
 
         return vscode.window.withProgress({
             title: `Setting up SSH Host ${sshDest.hostname}`,
             location: vscode.ProgressLocation.Notification,
-            cancellable: false
-        }, async (progress) => {
+            cancellable: true
+        }, async (progress, cancellation) => {
             try {
                 progress.report({ message: 'Connecting to SSH host...' });
                 const sshconfig = await SSHConfiguration.loadFromFS();
@@ -181,7 +187,7 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
                     identityFiles.some(f => fs.existsSync(untildify(f) + '-cert.pub'));
                 
                 let proxyStream: ssh2.ClientChannel | stream.Duplex | undefined;
-                const useNativeSSH = hasCertificate && !sshHostConfig['ProxyJump'];
+                const useNativeSSH = hasCertificate;
 
                 if (useNativeSSH) {
                     this.logger.info('Using native SSH connection (certificate detected)');
@@ -189,16 +195,137 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
                     const keyPath = identityFiles[0] ? untildify(identityFiles[0]) : `${os.homedir()}/.ssh/id_ecdsa`;
                     const certPath = fs.existsSync(keyPath + '-cert.pub') ? keyPath + '-cert.pub' : undefined;
                     
+                    const knownHostsFiles = (value: string | undefined) => value ? value.split(/\s+/).filter(f => !!f).map(f => untildify(f)) : undefined;
+                    const nativePrompts: Pick<NativeSSH.ConnectOptions, 'onHostKey' | 'onPassphrase' | 'onPassword' | 'onKeyboardInteractive'> = {
+                        onHostKey: async (prompt) => {
+                            const accept = 'Continue';
+                            const message = prompt.changed
+                                ? `WARNING: the ${prompt.keyType} host key for "${prompt.host}" has changed. Someone could be eavesdropping on you.`
+                                : `The authenticity of host "${prompt.host}" can't be established.`;
+                            const authority = prompt.certAuthority ? `\nThe host is covered by @cert-authority (${prompt.certAuthority}), which is not supported.` : '';
+                            const result = await vscode.window.showWarningMessage(message, { modal: true, detail: `${prompt.keyType} key fingerprint is ${prompt.fingerprint}.${authority}\n${prompt.randomart}` }, accept);
+                            return result === accept;
+                        },
+                        onPassphrase: async (prompt) => {
+                            const passphrase = await vscode.window.showInputBox({
+                                title: prompt.attempt > 1 ? `Bad passphrase, try again for ${prompt.keyPath}` : `Enter passphrase for ${prompt.keyPath}`,
+                                password: true,
+                                ignoreFocusOut: true
+                            });
+                            return passphrase ?? null;
+                        },
+                        onPassword: async (prompt) => {
+                            const password = await vscode.window.showInputBox({
+                                title: `Enter password for ${prompt.username}@${prompt.host}`,
+                                password: true,
+                                ignoreFocusOut: true
+                            });
+                            return password ?? null;
+                        },
+                        onKeyboardInteractive: async (request) => {
+                            const responses: string[] = [];
+                            for (const prompt of request.prompts) {
+                                const response = await vscode.window.showInputBox({
+                                    title: `(${request.username}@${request.host}) ${prompt.prompt}`,
+                                    password: !prompt.echo,
+                                    ignoreFocusOut: true
+                                });
+                                if (response === undefined) {
+                                    return null;
+                                }
+                                responses.push(response);
+                            }
+                            return responses;
+                        }
+                    };
+
+                    const jumpHosts: NativeSSH.JumpHost[] = [];
+                    for (const jump of (sshHostConfig['ProxyJump'] || '').split(',').filter(i => !!i.trim())) {
+                        const proxy = SSHDestination.parse(jump);
+                        const proxyHostConfig = sshconfig.getHostConfiguration(proxy.hostname);
+                        const proxyIdentityFiles: string[] = (proxyHostConfig['IdentityFile'] as unknown as string[]) || [];
+                        const proxyIdentitiesOnly = (proxyHostConfig['IdentitiesOnly'] || 'no').toLowerCase() === 'yes';
+                        const proxyIdentityKeys = await gatherIdentityFiles(proxyIdentityFiles, this.sshAgentSock, proxyIdentitiesOnly, this.logger);
+                        jumpHosts.push({
+                            host: proxyHostConfig['HostName'] || proxy.hostname,
+                            port: proxyHostConfig['Port'] ? parseInt(proxyHostConfig['Port'], 10) : (proxy.port || 22),
+                            username: proxyHostConfig['User'] || proxy.user || sshUser,
+                            identities: nativeIdentities(proxyIdentityFiles, proxyIdentityKeys, proxyIdentitiesOnly),
+                            options: {
+                                strictHostKeyChecking: proxyHostConfig['StrictHostKeyChecking'],
+                                userKnownHostsFiles: knownHostsFiles(proxyHostConfig['UserKnownHostsFile']),
+                                globalKnownHostsFiles: knownHostsFiles(proxyHostConfig['GlobalKnownHostsFile']),
+                                identityAgent: this.sshAgentSock,
+                                preferredAuthentications,
+                                serverAliveInterval: proxyHostConfig['ServerAliveInterval'] ? parseInt(proxyHostConfig['ServerAliveInterval'], 10) : undefined,
+                                serverAliveCountMax: proxyHostConfig['ServerAliveCountMax'] ? parseInt(proxyHostConfig['ServerAliveCountMax'], 10) : undefined,
+                                connectTimeoutMs: connectTimeout * 1000,
+                                handshakeTimeoutMs: connectTimeout * 1000,
+                                addressFamily: proxyHostConfig['AddressFamily']?.toLowerCase() as NativeSSH.ConnectOptions['addressFamily'],
+                                bindAddress: proxyHostConfig['BindAddress'],
+                                bindInterface: proxyHostConfig['BindInterface'],
+                                ciphers: proxyHostConfig['Ciphers'],
+                                kexAlgorithms: proxyHostConfig['KexAlgorithms'],
+                                macs: proxyHostConfig['MACs'],
+                                hostKeyAlgorithms: proxyHostConfig['HostKeyAlgorithms'],
+                                ...nativePrompts
+                            }
+                        });
+                    }
+
                     this.sshConnection = new NativeSSHConnection({
                         host: sshHostName,
                         port: sshPort,
                         username: sshUser,
                         keyPath,
-                        certPath
+                        certPath,
+                        identities: nativeIdentities(identityFiles, identityKeys, identitiesOnly),
+                        strictHostKeyChecking: sshHostConfig['StrictHostKeyChecking'],
+                        userKnownHostsFiles: knownHostsFiles(sshHostConfig['UserKnownHostsFile']),
+                        globalKnownHostsFiles: knownHostsFiles(sshHostConfig['GlobalKnownHostsFile']),
+                        identityAgent: this.sshAgentSock,
+                        forwardAgent: agentForward,
+                        preferredAuthentications,
+                        jumpHosts,
+                        proxyCommand: !jumpHosts.length && sshHostConfig['ProxyCommand'] && !proxyUseFdpass ? proxyCommandLine(sshHostConfig['ProxyCommand']) : undefined,
+                        hostAlias: sshDest.hostname,
+                        serverAliveInterval: sshHostConfig['ServerAliveInterval'] ? parseInt(sshHostConfig['ServerAliveInterval'], 10) : undefined,
+                        serverAliveCountMax: sshHostConfig['ServerAliveCountMax'] ? parseInt(sshHostConfig['ServerAliveCountMax'], 10) : undefined,
+                        // Keeps the tunnel across sleep and network changes.
+                        reconnect: reconnect ? {} : undefined,
+                        connectTimeoutMs: connectTimeout * 1000,
+                        handshakeTimeoutMs: connectTimeout * 1000,
+                        addressFamily: sshHostConfig['AddressFamily']?.toLowerCase() as NativeSSH.ConnectOptions['addressFamily'],
+                        bindAddress: sshHostConfig['BindAddress'],
+                        bindInterface: sshHostConfig['BindInterface'],
+                        ciphers: sshHostConfig['Ciphers'],
+                        kexAlgorithms: sshHostConfig['KexAlgorithms'],
+                        macs: sshHostConfig['MACs'],
+                        hostKeyAlgorithms: sshHostConfig['HostKeyAlgorithms'],
+                        ...nativePrompts
                     }, this.logger);
-                    
-                    await this.sshConnection.connect();
+
+                    const abort = new AbortController();
+                    const onCancel = cancellation.onCancellationRequested(() => abort.abort());
+                    try {
+                        await this.sshConnection.connect(abort.signal);
+                    } finally {
+                        onCancel.dispose();
+                    }
                 } else {
+                // ssh2 cannot abort a connection attempt, so cancelling stops
+                // waiting on it and closes whatever has been opened so far.
+                let onCancel: vscode.Disposable | undefined;
+                const cancelled = new Promise<never>((_, reject) => {
+                    onCancel = cancellation.onCancellationRequested(() => {
+                        this.proxyConnections.forEach(connection => connection.close());
+                        this.sshConnection?.close();
+                        this.proxyCommandProcess?.kill();
+                        reject(new Error('Connection cancelled'));
+                    });
+                });
+                cancelled.catch(() => { });
+                try {
                 if (sshHostConfig['ProxyJump']) {
                     const proxyJumps: ProxyJump[] = sshHostConfig['ProxyJump'].split(',').filter(i => !!i.trim())
                         .map((jump): ProxyJump => {
@@ -236,17 +363,13 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
                         const nextProxyJump = i < proxyJumps.length - 1 ? proxyJumps[i + 1] : undefined;
                         const destIP = nextProxyJump ? (nextProxyJump[1]['HostName'] || nextProxyJump[0].hostname) : sshHostName;
                         const destPort = nextProxyJump ? ((nextProxyJump[1]['Port'] && parseInt(nextProxyJump[1]['Port'], 10)) || nextProxyJump[0].port || 22) : sshPort;
-                        proxyStream = await proxyConnection.forwardOut('127.0.0.1', 0, destIP, destPort);
+                        proxyStream = await Promise.race([proxyConnection.forwardOut('127.0.0.1', 0, destIP, destPort), cancelled]);
                     }
                 } else if (sshHostConfig['ProxyCommand']) {
                     if (proxyUseFdpass) {
                         this.logger.trace('ProxyUseFdpass is enabled; skipping ProxyCommand and using direct TCP connection.');
                     } else {
-                    const proxyCommandValue = sshHostConfig['ProxyCommand'];
-                    const proxyCommandLine = Array.isArray(proxyCommandValue)
-                        ? proxyCommandValue.join(' ')
-                        : String(proxyCommandValue);
-                    const substituted = proxyCommandLine
+                    const substituted = proxyCommandLine(sshHostConfig['ProxyCommand'])
                         .replace(/%h/g, sshHostName)
                         .replace(/%n/g, sshDest.hostname)
                         .replace(/%p/g, sshPort.toString())
@@ -290,7 +413,10 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
                     agent,
                     authHandler: (arg0, arg1, arg2) => (sshAuthHandler(arg0, arg1, arg2), undefined),
                 });
-                await this.sshConnection.connect();
+                await Promise.race([this.sshConnection.connect(), cancelled]);
+                } finally {
+                    onCancel?.dispose();
+                }
                 }
 
                 const envVariables: Record<string, string | null> = {};
@@ -315,8 +441,7 @@ export class RemoteSSHResolver implements vscode.RemoteAuthorityResolver, vscode
                     }
                 }
 
-                // Only enable SOCKS for non-native connections
-                if (enableDynamicForwarding && !useNativeSSH) {
+                if (enableDynamicForwarding) {
                     progress.report({ message: 'Setting up port forwarding...' });
                     const socksPort = await findRandomPort();
                     this.socksTunnel = await this.sshConnection!.addTunnel({
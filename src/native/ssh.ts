@@ -1,29 +1,525 @@
-const { loadSshKeyInfo, testCertificateDetection, sshConnect, sshExec, sshForwardPort, sshUploadFile, sshDisconnect } = require('../uplink-ssh.darwin-arm64.node');
+import { EventEmitter } from 'events';
 
-export function loadSSHKeyInfo(keyPath: string): string {
-    return loadSshKeyInfo(keyPath);
+const { loadSshKeyInfo, testCertificateDetection, sshConnect, sshConnectWithIdentities, sshExec, sshExecStream, sshExecWithOptions, sshCreateCancelToken, sshCancel, sshCancelExec, sshOpenShell, sshShellWrite, sshShellResize, sshShellClose, sshForwardPort, sshDynamicForward, sshRemoteForward, sshCancelRemoteForward, sshForwardSocket, sshRemoteForwardSocket, sshCancelRemoteForwardSocket, sshListForwards, sshCloseForward, sshUploadFile, sshDownloadFile, sshUploadDir, sshDownloadDir, sshSftpStat, sshSftpLstat, sshSftpReaddir, sshSftpMkdir, sshSftpRmdir, sshSftpRemove, sshSftpRename, sshSftpSymlink, sshSftpReadlink, sshSftpRealpath, sshSftpChmod, sshSftpChown, sshSftpUtimes, sshSftpRead, sshDisconnect, sshSessionLog, sshListSessions, sshSessionInfo } = require('../uplink-ssh.darwin-arm64.node');
+
+export function loadSSHKeyInfo(keyPath: string, passphrase?: string): string {
+    return loadSshKeyInfo(keyPath, passphrase);
 }
 
 export function isCertificateFile(certPath: string): boolean {
     return testCertificateDetection(certPath);
 }
 
-export async function connect(host: string, port: number, username: string, keyPath: string, certPath?: string): Promise<number> {
-    return sshConnect(host, port, username, keyPath, certPath);
+export interface HostKeyPrompt {
+    host: string;
+    port: number;
+    keyType: string;
+    fingerprint: string;
+    randomart: string;
+    changed: boolean;
+    // Set when only an unsupported @cert-authority line covers the host.
+    certAuthority?: string;
+}
+
+export interface PassphrasePrompt {
+    keyPath: string;
+    attempt: number;
+}
+
+export interface PasswordPrompt {
+    username: string;
+    host: string;
+    attempt: number;
+}
+
+export interface KeyboardInteractivePrompt {
+    username: string;
+    host: string;
+    name: string;
+    instructions: string;
+    prompts: { prompt: string; echo: boolean }[];
+}
+
+export interface ConnectOptions {
+    strictHostKeyChecking?: string;
+    userKnownHostsFiles?: string[];
+    globalKnownHostsFiles?: string[];
+    onHostKey?: (prompt: HostKeyPrompt) => boolean | Promise<boolean>;
+    identityAgent?: string;
+    forwardAgent?: boolean;
+    passphrase?: string;
+    onPassphrase?: (prompt: PassphrasePrompt) => string | null | Promise<string | null>;
+    password?: string;
+    onPassword?: (prompt: PasswordPrompt) => string | null | Promise<string | null>;
+    onKeyboardInteractive?: (prompt: KeyboardInteractivePrompt) => string[] | null | Promise<string[] | null>;
+    preferredAuthentications?: string[];
+    jumpHosts?: JumpHost[];
+    proxyCommand?: string;
+    hostAlias?: string;
+    serverAliveInterval?: number;
+    serverAliveCountMax?: number;
+    // Fires when the connection drops or the server closes it; unless the
+    // session is reconnecting, its id is no longer valid by then.
+    onEvent?: (event: SessionEvent) => void;
+    // Connect again when the connection is lost, keeping the session id and
+    // its forwards.
+    reconnect?: ReconnectOptions;
+    // Limits on reaching the host and on the SSH handshake up to key
+    // exchange; unset waits indefinitely. Neither authentication nor host key
+    // prompts count against the handshake.
+    connectTimeoutMs?: number;
+    handshakeTimeoutMs?: number;
+    addressFamily?: 'inet' | 'inet6' | 'any';
+    bindAddress?: string;
+    bindInterface?: string;
+    // Abandons the connection attempt; the promise rejects with "Connect: cancelled".
+    signal?: AbortSignal;
+    // OpenSSH-style lists, e.g. "+ssh-rsa" or "^aes256-gcm@openssh.com".
+    ciphers?: string;
+    kexAlgorithms?: string;
+    macs?: string;
+    hostKeyAlgorithms?: string;
+}
+
+export interface ReconnectOptions {
+    attempts?: number;
+    delayMs?: number;
+    maxDelayMs?: number;
+}
+
+export interface SessionEvent {
+    sessionId: number;
+    kind: 'disconnected' | 'keepalive-timeout' | 'server-disconnect' | 'reconnecting' | 'reconnected' | 'reconnect-failed';
+    reasonCode?: number;
+    description?: string;
+    attempt?: number;
+    // False while the session is being reconnected.
+    closed: boolean;
+}
+
+export interface JumpHost {
+    host: string;
+    port?: number;
+    username: string;
+    identities?: Identity[];
+    options?: ConnectOptions;
+}
+
+// The native side calls prompt handlers Node-style, with an error argument
+// first, so that one that throws fails the connection instead of the process.
+function nativeOptions(options: ConnectOptions): object {
+    const { onHostKey, onPassphrase, onPassword, onKeyboardInteractive, jumpHosts, ...rest } = options;
+    return {
+        ...rest,
+        onHostKey: onHostKey && ((_: null, prompt: HostKeyPrompt) => onHostKey(prompt)),
+        onPassphrase: onPassphrase && ((_: null, prompt: PassphrasePrompt) => onPassphrase(prompt)),
+        onPassword: onPassword && ((_: null, prompt: PasswordPrompt) => onPassword(prompt)),
+        onKeyboardInteractive: onKeyboardInteractive && ((_: null, prompt: KeyboardInteractivePrompt) => onKeyboardInteractive(prompt)),
+        jumpHosts: jumpHosts?.map(hop => ({ ...hop, options: hop.options && nativeOptions(hop.options) })),
+    };
 }
 
-export async function exec(sessionId: number, command: string): Promise<string> {
+export async function connect(host: string, port: number, username: string, keyPath: string, certPath?: string, options: ConnectOptions = {}): Promise<number> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshConnect(host, port, username, keyPath, certPath, { ...nativeOptions(rest), cancelId }));
+}
+
+export interface Identity {
+    keyPath?: string;
+    certPath?: string;
+    agentFingerprint?: string;
+    agent?: boolean;
+}
+
+export interface AuthReport {
+    methods: string[];
+    identity?: string;
+    attempts: { identity: string; accepted: boolean; reason?: string }[];
+}
+
+export interface ConnectResult {
+    sessionId: number;
+    auth: AuthReport;
+}
+
+export async function connectWithIdentities(host: string, port: number, username: string, identities: Identity[], options: ConnectOptions = {}): Promise<ConnectResult> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshConnectWithIdentities(host, port, username, identities, { ...nativeOptions(rest), cancelId }));
+}
+
+export interface ExecExit {
+    exitCode?: number;
+    exitSignal?: string;
+    coreDumped: boolean;
+    errorMessage?: string;
+}
+
+export interface ExecResult extends ExecExit {
+    stdout: Buffer;
+    stderr: Buffer;
+}
+
+export async function exec(sessionId: number, command: string): Promise<ExecResult> {
     return sshExec(sessionId, command);
 }
 
-export async function forwardPort(sessionId: number, localPort: number, remoteHost: string, remotePort: number): Promise<number> {
+export async function execStream(sessionId: number, command: string, onStdout: (chunk: Buffer) => void, onStderr: (chunk: Buffer) => void): Promise<ExecExit> {
+    return sshExecStream(sessionId, command, onStdout, onStderr);
+}
+
+export interface ExecOptions {
+    stdin?: Buffer | string;
+    env?: Record<string, string>;
+    timeoutMs?: number;
+    pty?: boolean;
+    // With `pty`; defaults to "xterm-256color".
+    term?: string;
+    cols?: number;
+    rows?: number;
+    // Aborting sends `abortSignal` (default TERM) to the command and closes the channel.
+    signal?: AbortSignal;
+    abortSignal?: string;
+    onStdout?: (chunk: Buffer) => void;
+    onStderr?: (chunk: Buffer) => void;
+}
+
+// Bridges an AbortSignal to a native cancel token for the duration of `run`.
+async function withCancelToken<T>(signal: AbortSignal | undefined, cancel: (cancelId: number) => void, run: (cancelId?: number) => Promise<T>): Promise<T> {
+    if (!signal) {
+        return run();
+    }
+    signal.throwIfAborted();
+
+    const cancelId: number = sshCreateCancelToken();
+    const onAbort = () => cancel(cancelId);
+    signal.addEventListener('abort', onAbort, { once: true });
+    try {
+        return await run(cancelId);
+    } finally {
+        signal.removeEventListener('abort', onAbort);
+    }
+}
+
+export async function execWithOptions(sessionId: number, command: string, options: ExecOptions = {}): Promise<ExecResult> {
+    const { signal, abortSignal, stdin, ...rest } = options;
+    return withCancelToken(signal, cancelId => sshCancelExec(cancelId, abortSignal), cancelId => sshExecWithOptions(sessionId, command, {
+        ...rest,
+        stdin: typeof stdin === 'string' ? Buffer.from(stdin) : stdin,
+        cancelId,
+        cancelSignal: abortSignal,
+    }));
+}
+
+export interface ShellOptions {
+    term?: string;
+    cols?: number;
+    rows?: number;
+    modes?: Record<string, number>;
+}
+
+// Emits 'data' (Buffer) as the remote terminal writes and 'exit' (ExecExit)
+// once the channel has closed.
+export class Shell extends EventEmitter {
+    private constructor(private readonly shellId: number) {
+        super();
+    }
+
+    static async open(sessionId: number, options: ShellOptions = {}): Promise<Shell> {
+        let shell: Shell | undefined;
+        let pending: [string, unknown][] | undefined = [];
+        const emit = (event: string, value: unknown) => pending ? pending.push([event, value]) : shell!.emit(event, value);
+
+        const shellId: number = await sshOpenShell(
+            sessionId,
+            options,
+            (chunk: Buffer) => emit('data', chunk),
+            (exit: ExecExit) => emit('exit', exit)
+        );
+        shell = new Shell(shellId);
+        // Output that raced the open call is replayed once listeners can be attached.
+        setImmediate(() => {
+            const queued = pending!;
+            pending = undefined;
+            queued.forEach(([event, value]) => shell!.emit(event, value));
+        });
+        return shell;
+    }
+
+    write(data: Buffer | string): Promise<void> {
+        return sshShellWrite(this.shellId, typeof data === 'string' ? Buffer.from(data) : data);
+    }
+
+    resize(cols: number, rows: number): Promise<void> {
+        return sshShellResize(this.shellId, cols, rows);
+    }
+
+    close(): Promise<void> {
+        return sshShellClose(this.shellId);
+    }
+}
+
+export async function forwardPort(sessionId: number, localPort: number, remoteHost: string, remotePort: number): Promise<ForwardInfo> {
     return sshForwardPort(sessionId, localPort, remoteHost, remotePort);
 }
 
+export async function dynamicForward(sessionId: number, bindAddr: string, port: number): Promise<ForwardInfo> {
+    return sshDynamicForward(sessionId, bindAddr, port);
+}
+
+export async function remoteForward(sessionId: number, remoteAddr: string, remotePort: number, localHost: string, localPort: number): Promise<ForwardInfo> {
+    return sshRemoteForward(sessionId, remoteAddr, remotePort, localHost, localPort);
+}
+
+export async function cancelRemoteForward(sessionId: number, remoteAddr: string, remotePort: number): Promise<void> {
+    return sshCancelRemoteForward(sessionId, remoteAddr, remotePort);
+}
+
+export async function forwardSocket(sessionId: number, localPort: number, remoteSocketPath: string): Promise<ForwardInfo> {
+    return sshForwardSocket(sessionId, localPort, remoteSocketPath);
+}
+
+export async function remoteForwardSocket(sessionId: number, remoteSocketPath: string, localHost: string, localPort: number): Promise<ForwardInfo> {
+    return sshRemoteForwardSocket(sessionId, remoteSocketPath, localHost, localPort);
+}
+
+export async function cancelRemoteForwardSocket(sessionId: number, remoteSocketPath: string): Promise<void> {
+    return sshCancelRemoteForwardSocket(sessionId, remoteSocketPath);
+}
+
+export interface ForwardInfo {
+    forwardId: number;
+    sessionId: number;
+    kind: string;
+    listenAddress: string;
+    listenPort?: number;
+    target: string;
+    activeConnections: number;
+    totalConnections: number;
+    bytesSent: number;
+    bytesReceived: number;
+}
+
+export function listForwards(sessionId?: number): ForwardInfo[] {
+    return sshListForwards(sessionId);
+}
+
+export async function closeForward(forwardId: number): Promise<void> {
+    return sshCloseForward(forwardId);
+}
+
 export async function disconnect(sessionId: number): Promise<void> {
     return sshDisconnect(sessionId);
 }
 
-export async function uploadFile(sessionId: number, localPath: string, remotePath: string): Promise<void> {
-    return sshUploadFile(sessionId, localPath, remotePath);
-}
\ No newline at end of file
+export interface SftpStat {
+    size?: number;
+    uid?: number;
+    gid?: number;
+    mode?: number;
+    atime?: number;
+    mtime?: number;
+    isFile: boolean;
+    isDirectory: boolean;
+    isSymlink: boolean;
+}
+
+export interface SftpEntry {
+    name: string;
+    longName: string;
+    stat: SftpStat;
+}
+
+// All sftp calls share one subsystem channel per session, opened on first use.
+export async function sftpStat(sessionId: number, path: string): Promise<SftpStat | null> {
+    return sshSftpStat(sessionId, path);
+}
+
+export async function sftpLstat(sessionId: number, path: string): Promise<SftpStat | null> {
+    return sshSftpLstat(sessionId, path);
+}
+
+export async function sftpReaddir(sessionId: number, path: string): Promise<SftpEntry[]> {
+    return sshSftpReaddir(sessionId, path);
+}
+
+export async function sftpMkdir(sessionId: number, path: string, options: { recursive?: boolean; mode?: number } = {}): Promise<void> {
+    return sshSftpMkdir(sessionId, path, options.recursive, options.mode);
+}
+
+export async function sftpRmdir(sessionId: number, path: string): Promise<void> {
+    return sshSftpRmdir(sessionId, path);
+}
+
+export async function sftpRemove(sessionId: number, path: string): Promise<void> {
+    return sshSftpRemove(sessionId, path);
+}
+
+export async function sftpRename(sessionId: number, oldPath: string, newPath: string): Promise<void> {
+    return sshSftpRename(sessionId, oldPath, newPath);
+}
+
+export async function sftpSymlink(sessionId: number, target: string, linkPath: string): Promise<void> {
+    return sshSftpSymlink(sessionId, target, linkPath);
+}
+
+export async function sftpReadlink(sessionId: number, path: string): Promise<string> {
+    return sshSftpReadlink(sessionId, path);
+}
+
+export async function sftpRealpath(sessionId: number, path: string): Promise<string> {
+    return sshSftpRealpath(sessionId, path);
+}
+
+export async function sftpChmod(sessionId: number, path: string, mode: number): Promise<void> {
+    return sshSftpChmod(sessionId, path, mode);
+}
+
+export async function sftpChown(sessionId: number, path: string, uid: number, gid: number): Promise<void> {
+    return sshSftpChown(sessionId, path, uid, gid);
+}
+
+export async function sftpUtimes(sessionId: number, path: string, atime: number, mtime: number): Promise<void> {
+    return sshSftpUtimes(sessionId, path, atime, mtime);
+}
+
+export async function sftpRead(sessionId: number, path: string, offset: number, length: number): Promise<Buffer> {
+    return sshSftpRead(sessionId, path, offset, length);
+}
+
+export interface TransferProgress {
+    transferred: number;
+    total: number;
+}
+
+export interface TransferResult {
+    size: number;
+    transferred: number;
+    resumedFrom: number;
+    sha256?: string;
+    verifiedWith?: string;
+}
+
+export interface TransferOptions {
+    resume?: boolean;
+    requests?: number;
+    // Compare a SHA-256 computed while streaming with the remote file's; a
+    // mismatch rejects with IntegrityError, after an upload has removed the
+    // remote copy it wrote.
+    verify?: boolean;
+    // Uploads only: write a temporary sibling and rename it over the target
+    // once complete, optionally keeping the target's permissions.
+    atomic?: boolean;
+    keepPermissions?: boolean;
+    signal?: AbortSignal;
+    onProgress?: (progress: TransferProgress) => void;
+}
+
+// The transfer completed but the two copies differ; retrying is reasonable.
+export class IntegrityError extends Error {
+    constructor(message: string, readonly path: string, readonly localSha256: string, readonly remoteSha256: string) {
+        super(message);
+        this.name = 'IntegrityError';
+    }
+}
+
+interface IntegrityMismatch {
+    localSha256: string;
+    remoteSha256: string;
+}
+
+// Native transfers report a mismatch in their result rather than failing.
+function checkIntegrity(remotePath: string, result: TransferResult & { mismatch?: IntegrityMismatch }): TransferResult {
+    const { mismatch, ...rest } = result;
+    if (mismatch) {
+        const message = `Integrity mismatch: ${remotePath}: local sha256 ${mismatch.localSha256}, remote sha256 ${mismatch.remoteSha256}`;
+        throw new IntegrityError(message, remotePath, mismatch.localSha256, mismatch.remoteSha256);
+    }
+    return rest;
+}
+
+export async function uploadFile(sessionId: number, localPath: string, remotePath: string, options: TransferOptions = {}): Promise<TransferResult> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshUploadFile(sessionId, localPath, remotePath, { ...rest, cancelId }))
+        .then(result => checkIntegrity(remotePath, result));
+}
+
+export async function downloadFile(sessionId: number, remotePath: string, localPath: string, options: TransferOptions = {}): Promise<TransferResult> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshDownloadFile(sessionId, remotePath, localPath, { ...rest, cancelId }))
+        .then(result => checkIntegrity(remotePath, result));
+}
+
+export interface SyncProgress {
+    filesDone: number;
+    filesTotal: number;
+    bytesDone: number;
+    bytesTotal: number;
+}
+
+export interface SyncResult {
+    filesTransferred: number;
+    filesSkipped: number;
+    bytesTransferred: number;
+    directoriesCreated: number;
+}
+
+export interface SyncOptions {
+    // Globs relative to the source directory; a pattern without '/' matches
+    // names at any depth. Excluded directories are not descended into.
+    include?: string[];
+    exclude?: string[];
+    preserve?: boolean;
+    delta?: 'size-mtime' | 'hash';
+    concurrency?: number;
+    requests?: number;
+    signal?: AbortSignal;
+    onProgress?: (progress: SyncProgress) => void;
+}
+
+export async function uploadDir(sessionId: number, localDir: string, remoteDir: string, options: SyncOptions = {}): Promise<SyncResult> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshUploadDir(sessionId, localDir, remoteDir, { ...rest, cancelId }));
+}
+
+export async function downloadDir(sessionId: number, remoteDir: string, localDir: string, options: SyncOptions = {}): Promise<SyncResult> {
+    const { signal, ...rest } = options;
+    return withCancelToken(signal, sshCancel, cancelId => sshDownloadDir(sessionId, remoteDir, localDir, { ...rest, cancelId }));
+}
+
+export function sessionLog(sessionId: number): string[] {
+    return sshSessionLog(sessionId);
+}
+
+export interface SessionInfo {
+    sessionId: number;
+    host: string;
+    port: number;
+    username: string;
+    authMethods: string[];
+    identity?: string;
+    jumpHosts: string[];
+    // Milliseconds since the epoch.
+    startedAt: number;
+    forwards: ForwardInfo[];
+    shells: number;
+    execs: number;
+    sftp: boolean;
+    channels: number;
+    algorithms?: NegotiatedAlgorithms;
+}
+
+export interface NegotiatedAlgorithms {
+    kex: string;
+    hostKey: string;
+    cipher: string;
+    // Unset for AEAD ciphers.
+    mac?: string;
+}
+
+export function listSessions(): SessionInfo[] {
+    return sshListSessions();
+}
+
+export function sessionInfo(sessionId: number): SessionInfo {
+    return sshSessionInfo(sessionId);
+}
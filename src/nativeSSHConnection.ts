@@ -8,6 +8,32 @@ export interface NativeSSHConfig {
     username: string;
     keyPath: string;
     certPath?: string;
+    identities?: NativeSSH.Identity[];
+    strictHostKeyChecking?: string;
+    userKnownHostsFiles?: string[];
+    globalKnownHostsFiles?: string[];
+    onHostKey?: (prompt: NativeSSH.HostKeyPrompt) => boolean | Promise<boolean>;
+    identityAgent?: string;
+    forwardAgent?: boolean;
+    onPassphrase?: (prompt: NativeSSH.PassphrasePrompt) => string | null | Promise<string | null>;
+    onPassword?: (prompt: NativeSSH.PasswordPrompt) => string | null | Promise<string | null>;
+    onKeyboardInteractive?: (prompt: NativeSSH.KeyboardInteractivePrompt) => string[] | null | Promise<string[] | null>;
+    preferredAuthentications?: string[];
+    jumpHosts?: NativeSSH.JumpHost[];
+    proxyCommand?: string;
+    hostAlias?: string;
+    serverAliveInterval?: number;
+    serverAliveCountMax?: number;
+    reconnect?: NativeSSH.ReconnectOptions;
+    connectTimeoutMs?: number;
+    handshakeTimeoutMs?: number;
+    addressFamily?: 'inet' | 'inet6' | 'any';
+    bindAddress?: string;
+    bindInterface?: string;
+    ciphers?: string;
+    kexAlgorithms?: string;
+    macs?: string;
+    hostKeyAlgorithms?: string;
 }
 
 export interface SSHTunnelConfig {
@@ -23,7 +49,7 @@ export class NativeSSHConnection extends EventEmitter {
     private sessionId: number | null = null;
     private config: NativeSSHConfig;
     private logger: Log;
-    private tunnels: Map<string, { localPort: number }> = new Map();
+    private tunnels: Map<string, { localPort: number; forwardId: number }> = new Map();
 
     constructor(config: NativeSSHConfig, logger: Log) {
         super();
@@ -31,34 +57,149 @@ export class NativeSSHConnection extends EventEmitter {
         this.logger = logger;
     }
 
-    async connect(): Promise<NativeSSHConnection> {
+    async connect(signal?: AbortSignal): Promise<NativeSSHConnection> {
         if (this.sessionId !== null) {
             return this;
         }
 
         this.logger.trace(`Native SSH connecting to ${this.config.host}:${this.config.port}`);
         
-        this.sessionId = await NativeSSH.connect(
-            this.config.host,
-            this.config.port,
-            this.config.username,
-            this.config.keyPath,
-            this.config.certPath
-        );
+        const options: NativeSSH.ConnectOptions = {
+            strictHostKeyChecking: this.config.strictHostKeyChecking,
+            userKnownHostsFiles: this.config.userKnownHostsFiles,
+            globalKnownHostsFiles: this.config.globalKnownHostsFiles,
+            onHostKey: this.config.onHostKey,
+            identityAgent: this.config.identityAgent,
+            forwardAgent: this.config.forwardAgent,
+            onPassphrase: this.config.onPassphrase,
+            onPassword: this.config.onPassword,
+            onKeyboardInteractive: this.config.onKeyboardInteractive,
+            preferredAuthentications: this.config.preferredAuthentications,
+            jumpHosts: this.config.jumpHosts,
+            proxyCommand: this.config.proxyCommand,
+            hostAlias: this.config.hostAlias,
+            serverAliveInterval: this.config.serverAliveInterval,
+            serverAliveCountMax: this.config.serverAliveCountMax,
+            reconnect: this.config.reconnect,
+            connectTimeoutMs: this.config.connectTimeoutMs,
+            handshakeTimeoutMs: this.config.handshakeTimeoutMs,
+            addressFamily: this.config.addressFamily,
+            bindAddress: this.config.bindAddress,
+            bindInterface: this.config.bindInterface,
+            ciphers: this.config.ciphers,
+            kexAlgorithms: this.config.kexAlgorithms,
+            macs: this.config.macs,
+            hostKeyAlgorithms: this.config.hostKeyAlgorithms,
+            signal,
+            onEvent: event => this.onSessionEvent(event)
+        };
+
+        if (this.config.identities) {
+            const result = await NativeSSH.connectWithIdentities(this.config.host, this.config.port, this.config.username, this.config.identities, options);
+            for (const attempt of result.auth.attempts) {
+                this.logger.trace(`Native SSH identity ${attempt.identity}: ${attempt.accepted ? 'accepted' : attempt.reason}`);
+            }
+            this.sessionId = result.sessionId;
+        } else {
+            this.sessionId = await NativeSSH.connect(this.config.host, this.config.port, this.config.username, this.config.keyPath, this.config.certPath, options);
+        }
 
         this.logger.trace(`Native SSH connected, session ID: ${this.sessionId}`);
+        const algorithms = NativeSSH.sessionInfo(this.sessionId!).algorithms;
+        if (algorithms) {
+            this.logger.trace(`Native SSH algorithms: kex ${algorithms.kex}, host key ${algorithms.hostKey}, cipher ${algorithms.cipher}, mac ${algorithms.mac ?? 'implicit'}`);
+        }
         return this;
     }
 
-    async exec(cmd: string): Promise<{ stdout: string; stderr: string }> {
+    // Once the native side has dropped the session, forget it so that the
+    // next call connects afresh. While it reconnects the id stays valid.
+    private onSessionEvent(event: NativeSSH.SessionEvent): void {
+        if (event.sessionId !== this.sessionId) {
+            return;
+        }
+        const attempt = event.attempt ? ` #${event.attempt}` : '';
+        this.logger.trace(`Native SSH session ${event.sessionId}: ${event.kind}${attempt}${event.description ? `: ${event.description}` : ''}`);
+        if (event.closed) {
+            this.sessionId = null;
+            this.tunnels.clear();
+        }
+        this.emit(event.closed ? 'disconnected' : 'reconnect', event);
+    }
+
+    async exec(cmd: string): Promise<{ stdout: string; stderr: string; exitCode?: number; exitSignal?: string }> {
         await this.connect();
         
         if (this.sessionId === null) {
             throw new Error('Not connected');
         }
 
-        const output = await NativeSSH.exec(this.sessionId, cmd);
-        return { stdout: output, stderr: '' };
+        const result = await NativeSSH.exec(this.sessionId, cmd);
+        return {
+            stdout: result.stdout.toString(),
+            stderr: result.stderr.toString(),
+            exitCode: result.exitCode,
+            exitSignal: result.exitSignal
+        };
+    }
+
+    async execStream(cmd: string, onOutput: (chunk: string, stream: 'stdout' | 'stderr') => void): Promise<{ stdout: string; stderr: string; exitCode?: number; exitSignal?: string }> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        const stdout: Buffer[] = [];
+        const stderr: Buffer[] = [];
+        const exit = await NativeSSH.execStream(
+            this.sessionId,
+            cmd,
+            chunk => {
+                stdout.push(chunk);
+                onOutput(chunk.toString(), 'stdout');
+            },
+            chunk => {
+                stderr.push(chunk);
+                onOutput(chunk.toString(), 'stderr');
+            }
+        );
+        return {
+            stdout: Buffer.concat(stdout).toString(),
+            stderr: Buffer.concat(stderr).toString(),
+            exitCode: exit.exitCode,
+            exitSignal: exit.exitSignal
+        };
+    }
+
+    async execWithOptions(cmd: string, options: Omit<NativeSSH.ExecOptions, 'onStdout' | 'onStderr'>, onOutput?: (chunk: string, stream: 'stdout' | 'stderr') => void): Promise<{ stdout: string; stderr: string; exitCode?: number; exitSignal?: string }> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        const result = await NativeSSH.execWithOptions(this.sessionId, cmd, {
+            ...options,
+            onStdout: onOutput && (chunk => onOutput(chunk.toString(), 'stdout')),
+            onStderr: onOutput && (chunk => onOutput(chunk.toString(), 'stderr'))
+        });
+        return {
+            stdout: result.stdout.toString(),
+            stderr: result.stderr.toString(),
+            exitCode: result.exitCode,
+            exitSignal: result.exitSignal
+        };
+    }
+
+    async openShell(options: NativeSSH.ShellOptions = {}): Promise<NativeSSH.Shell> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        return NativeSSH.Shell.open(this.sessionId, options);
     }
 
     async addTunnel(config: SSHTunnelConfig): Promise<SSHTunnelConfig & { localPort: number }> {
@@ -68,54 +209,160 @@ export class NativeSSHConnection extends EventEmitter {
             throw new Error('Not connected');
         }
 
-        const name = config.name || `${config.remoteAddr}:${config.remotePort}`;
+        const name = config.name || (config.remoteSocketPath ? config.remoteSocketPath : `${config.remoteAddr}:${config.remotePort}`);
         
         if (this.tunnels.has(name)) {
             const existing = this.tunnels.get(name)!;
             return { ...config, localPort: existing.localPort };
         }
 
+        let forward: NativeSSH.ForwardInfo;
         if (config.socks) {
-            throw new Error('SOCKS tunnels not supported in native SSH connection');
+            forward = await NativeSSH.dynamicForward(this.sessionId, '127.0.0.1', config.localPort || 0);
+        } else if (config.remoteSocketPath) {
+            forward = await NativeSSH.forwardSocket(this.sessionId, config.localPort || 0, config.remoteSocketPath);
+        } else {
+            forward = await NativeSSH.forwardPort(
+                this.sessionId,
+                config.localPort || 0,
+                config.remoteAddr || '127.0.0.1',
+                config.remotePort || 0
+            );
         }
 
-        const localPort = await NativeSSH.forwardPort(
-            this.sessionId,
-            config.localPort || 0,
-            config.remoteAddr || '127.0.0.1',
-            config.remotePort || 0
-        );
-
-        this.tunnels.set(name, { localPort });
+        const localPort = forward.listenPort!;
+        this.tunnels.set(name, { localPort, forwardId: forward.forwardId });
         this.logger.trace(`Native SSH tunnel created: ${name} -> localhost:${localPort}`);
 
         return { ...config, localPort };
     }
 
-    closeTunnel(name?: string): Promise<void> {
-        if (name) {
-            this.tunnels.delete(name);
-        } else {
-            this.tunnels.clear();
+    async closeTunnel(name?: string): Promise<void> {
+        const names = name ? [name] : [...this.tunnels.keys()];
+        for (const tunnelName of names) {
+            const tunnel = this.tunnels.get(tunnelName);
+            this.tunnels.delete(tunnelName);
+            if (tunnel) {
+                await NativeSSH.closeForward(tunnel.forwardId);
+            }
+        }
+    }
+
+    async forwardIn(remoteAddr: string, remotePort: number, localHost: string, localPort: number): Promise<number> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        const forward = await NativeSSH.remoteForward(this.sessionId, remoteAddr, remotePort, localHost, localPort);
+        const boundPort = forward.listenPort!;
+        this.logger.trace(`Native SSH remote forward created: ${remoteAddr}:${boundPort} -> ${localHost}:${localPort}`);
+        return boundPort;
+    }
+
+    async unforwardIn(remoteAddr: string, remotePort: number): Promise<void> {
+        if (this.sessionId !== null) {
+            await NativeSSH.cancelRemoteForward(this.sessionId, remoteAddr, remotePort);
+        }
+    }
+
+    async forwardInStreamLocal(remoteSocketPath: string, localHost: string, localPort: number): Promise<void> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        await NativeSSH.remoteForwardSocket(this.sessionId, remoteSocketPath, localHost, localPort);
+        this.logger.trace(`Native SSH remote forward created: ${remoteSocketPath} -> ${localHost}:${localPort}`);
+    }
+
+    async unforwardInStreamLocal(remoteSocketPath: string): Promise<void> {
+        if (this.sessionId !== null) {
+            await NativeSSH.cancelRemoteForwardSocket(this.sessionId, remoteSocketPath);
         }
-        return Promise.resolve();
+    }
+
+    info(): NativeSSH.SessionInfo | null {
+        return this.sessionId === null ? null : NativeSSH.sessionInfo(this.sessionId);
     }
 
     async close(): Promise<void> {
         if (this.sessionId !== null) {
+            for (const line of NativeSSH.sessionLog(this.sessionId)) {
+                this.logger.trace(`Native SSH log: ${line}`);
+            }
             await NativeSSH.disconnect(this.sessionId);
             this.sessionId = null;
             this.tunnels.clear();
         }
     }
 
-    async uploadFile(localPath: string, remotePath: string): Promise<void> {
+    async stat(remotePath: string): Promise<NativeSSH.SftpStat | null> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        return NativeSSH.sftpStat(this.sessionId, remotePath);
+    }
+
+    async mkdir(remotePath: string, options: { recursive?: boolean; mode?: number } = {}): Promise<void> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        await NativeSSH.sftpMkdir(this.sessionId, remotePath, options);
+    }
+
+    // Like `rm -f`: a missing file is not an error.
+    async removeFile(remotePath: string): Promise<void> {
+        if (await this.stat(remotePath) !== null) {
+            await NativeSSH.sftpRemove(this.sessionId!, remotePath);
+        }
+    }
+
+    async uploadFile(localPath: string, remotePath: string, options?: NativeSSH.TransferOptions): Promise<NativeSSH.TransferResult> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        return NativeSSH.uploadFile(this.sessionId, localPath, remotePath, options);
+    }
+
+    async downloadFile(remotePath: string, localPath: string, options?: NativeSSH.TransferOptions): Promise<NativeSSH.TransferResult> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        return NativeSSH.downloadFile(this.sessionId, remotePath, localPath, options);
+    }
+
+    async uploadDir(localDir: string, remoteDir: string, options?: NativeSSH.SyncOptions): Promise<NativeSSH.SyncResult> {
+        await this.connect();
+
+        if (this.sessionId === null) {
+            throw new Error('Not connected');
+        }
+
+        return NativeSSH.uploadDir(this.sessionId, localDir, remoteDir, options);
+    }
+
+    async downloadDir(remoteDir: string, localDir: string, options?: NativeSSH.SyncOptions): Promise<NativeSSH.SyncResult> {
         await this.connect();
 
         if (this.sessionId === null) {
             throw new Error('Not connected');
         }
 
-        await NativeSSH.uploadFile(this.sessionId, localPath, remotePath);
+        return NativeSSH.downloadDir(this.sessionId, remoteDir, localDir, options);
     }
 }
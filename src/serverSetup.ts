@@ -5,6 +5,8 @@ import * as vscode from 'vscode';
 import Log from './common/logger';
 import { getVSCodeServerConfig } from './serverConfig';
 import SSHConnection from './ssh/sshConnection';
+import { NativeSSHConnection } from './nativeSSHConnection';
+import * as NativeSSH from './native/ssh';
 import { getOrDownloadServerOnRemote } from './serverDownload';
 
 export interface ServerInstallOptions {
@@ -90,11 +92,15 @@ export async function installCodeServer(
             if (progress) {
                 progress.report({ message: 'Uploading server archive...' });
             }
-            await conn.exec(`mkdir -p ${shellEscape(serverDir)}`);
+            await makeRemoteDir(conn, serverDir);
             logger.trace(`Uploading server archive to ${remoteArchivePath}`);
-            await conn.exec(`rm -f ${shellEscape(remoteArchivePath)}`);
-            await conn.uploadFile(localArchivePath, remoteArchivePath);
-            await verifyRemoteArchiveSize(conn, localArchivePath, remoteArchivePath, localArchiveSize, logger);
+            await removeRemoteFile(conn, remoteArchivePath);
+            if (conn instanceof NativeSSHConnection) {
+                await uploadVerifiedArchive(conn, localArchivePath, remoteArchivePath, logger, progress);
+            } else {
+                await conn.uploadFile(localArchivePath, remoteArchivePath);
+                await verifyRemoteArchiveSize(conn, localArchivePath, remoteArchivePath, localArchiveSize, logger);
+            }
         }
     } else {
         remoteArchivePath = `${serverDir}/server.tar.gz`;
@@ -120,7 +126,10 @@ export async function installCodeServer(
 
     logger.trace('Server install command:', installServerScript);
 
-    const commandOutput = await conn.exec(`bash -c '${installServerScript.replace(/'/g, `'\\''`)}'`);
+    // Native sessions feed the script on stdin, so it needs no shell quoting.
+    const commandOutput = conn instanceof NativeSSHConnection
+        ? await conn.execWithOptions('bash -s', { stdin: installServerScript }, (chunk, stream) => logger.trace(`Server install ${stream}:`, chunk.trimEnd()))
+        : await conn.exec(`bash -c '${installServerScript.replace(/'/g, `'\\''`)}'`);
 
     if (commandOutput.stderr) {
         logger.trace('Server install command stderr:', commandOutput.stderr);
@@ -201,16 +210,58 @@ async function resolveRemoteHomeDir(conn: SSHConnection): Promise<string> {
 }
 
 async function remoteFileExists(conn: SSHConnection, remotePath: string): Promise<boolean> {
+    if (conn instanceof NativeSSHConnection) {
+        return (await conn.stat(remotePath))?.isFile ?? false;
+    }
     const result = await conn.exec(`if [ -f ${shellEscape(remotePath)} ]; then echo yes; fi`);
     return result.stdout.trim() === 'yes';
 }
 
 async function getRemoteFileSize(conn: SSHConnection, remotePath: string): Promise<number | undefined> {
+    if (conn instanceof NativeSSHConnection) {
+        const stat = await conn.stat(remotePath);
+        return stat?.isFile ? stat.size : undefined;
+    }
     const result = await conn.exec(`if [ -f ${shellEscape(remotePath)} ]; then wc -c < ${shellEscape(remotePath)}; fi`);
     const size = parseInt(result.stdout.trim(), 10);
     return Number.isFinite(size) ? size : undefined;
 }
 
+// Native uploads hash the archive while streaming and check it against the
+// remote copy, so a corrupted upload is caught without a separate size check.
+async function uploadVerifiedArchive(
+    conn: NativeSSHConnection,
+    localArchivePath: string,
+    remoteArchivePath: string,
+    logger: Log,
+    progress?: vscode.Progress<{ message?: string; increment?: number }>
+): Promise<void> {
+    const upload = () => conn.uploadFile(localArchivePath, remoteArchivePath, {
+        verify: true,
+        atomic: true,
+        onProgress: ({ transferred, total }) => progress?.report({ message: `Uploading server archive (${Math.floor(transferred * 100 / Math.max(total, 1))}%)...` })
+    });
+
+    try {
+        const result = await upload();
+        logger.trace(`Sidecar archive verified (sha256 ${result.sha256} via ${result.verifiedWith}).`);
+    } catch (error) {
+        if (!(error instanceof NativeSSH.IntegrityError)) {
+            throw error;
+        }
+        logger.trace(`Sidecar archive hash mismatch (local ${error.localSha256}, remote ${error.remoteSha256}). Retrying upload.`);
+        try {
+            await upload();
+        } catch (retryError) {
+            if (retryError instanceof NativeSSH.IntegrityError) {
+                throw new ServerInstallError(`Sidecar archive upload corrupted (local sha256 ${retryError.localSha256}, remote ${retryError.remoteSha256}).`);
+            }
+            throw retryError;
+        }
+        logger.trace('Sidecar archive verified after retry.');
+    }
+}
+
 async function verifyRemoteArchiveSize(
     conn: SSHConnection,
     localArchivePath: string,
@@ -227,7 +278,7 @@ async function verifyRemoteArchiveSize(
     logger.trace(
         `Sidecar archive size mismatch (local ${localArchiveSize} bytes, remote ${remoteSize ?? 'missing'}). Retrying upload.`
     );
-    await conn.exec(`rm -f ${shellEscape(remoteArchivePath)}`);
+    await removeRemoteFile(conn, remoteArchivePath);
     await conn.uploadFile(localArchivePath, remoteArchivePath);
 
     const retrySize = await getRemoteFileSize(conn, remoteArchivePath);
@@ -239,6 +290,22 @@ async function verifyRemoteArchiveSize(
     logger.trace(`Sidecar archive size verified after retry (${localArchiveSize} bytes).`);
 }
 
+async function makeRemoteDir(conn: SSHConnection, remotePath: string): Promise<void> {
+    if (conn instanceof NativeSSHConnection) {
+        await conn.mkdir(remotePath, { recursive: true });
+        return;
+    }
+    await conn.exec(`mkdir -p ${shellEscape(remotePath)}`);
+}
+
+async function removeRemoteFile(conn: SSHConnection, remotePath: string): Promise<void> {
+    if (conn instanceof NativeSSHConnection) {
+        await conn.removeFile(remotePath);
+        return;
+    }
+    await conn.exec(`rm -f ${shellEscape(remotePath)}`);
+}
+
 function shellEscape(value: string): string {
     return `'${value.replace(/'/g, `'\\''`)}'`;
 }
@@ -6,7 +6,7 @@ import type { ParsedKey } from 'ssh2-streams';
 import * as ssh2 from 'ssh2';
 import { untildify, exists as fileExists } from '../common/files';
 import Log from '../common/logger';
-import { loadSSHKeyInfo, isCertificateFile } from '../native/ssh';
+import { loadSSHKeyInfo, isCertificateFile, Identity } from '../native/ssh';
 
 const homeDir = os.homedir();
 const PATH_SSH_CLIENT_ID_DSA = path.join(homeDir, '.ssh', '/id_dsa');
@@ -153,3 +153,28 @@ export async function gatherIdentityFiles(identityFiles: string[], sshAgentSock:
 
     return sortedKeys;
 }
+
+// Orders identities for the native client the same way as the ssh2 path. Key
+// files ssh2 could not parse (usually encrypted ones) are still offered last.
+export function nativeIdentities(identityFiles: string[], identityKeys: SSHKey[], identitiesOnly: boolean): Identity[] {
+    const withCert = (keyPath: string): Identity => ({
+        keyPath,
+        certPath: fs.existsSync(keyPath + '-cert.pub') ? keyPath + '-cert.pub' : undefined
+    });
+
+    const identities: Identity[] = identityKeys.map(k => k.isPrivate
+        ? { ...withCert(k.filename), agentFingerprint: k.agentSupport ? k.fingerprint : undefined }
+        : { agentFingerprint: k.fingerprint });
+
+    const offered = new Set(identityKeys.filter(k => k.isPrivate).map(k => k.filename));
+    for (const keyPath of identityFiles.map(untildify).map(i => i.replace(/\.pub$/, ''))) {
+        if (!offered.has(keyPath) && fs.existsSync(keyPath)) {
+            identities.push(withCert(keyPath));
+        }
+    }
+
+    if (!identitiesOnly) {
+        identities.push({ agent: true });
+    }
+    return identities;
+}